@@ -0,0 +1,227 @@
+use crate::hitables::*;
+use crate::mylib::*;
+use std::sync::Arc;
+
+/// How the two objects of a Csg are combined
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOp {
+    /// Inside either of the two
+    Union,
+    /// Inside both
+    Intersection,
+    /// Inside the first but not the second, the second one carves
+    /// its shape out of the first
+    Difference,
+}
+
+impl CsgOp {
+    fn inside(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// Constructive solid geometry, the combination of two closed objects.
+/// Both sides are asked for every intersection along the ray and we
+/// walk through them keeping track of whether we are inside each one,
+/// the surface of the result is wherever the combined inside changes.
+/// Csg nodes can be nested, and Transforms can be used on both sides
+pub struct Csg {
+    op: CsgOp,
+    left: Arc<dyn Hitable + Sync + Send>,
+    right: Arc<dyn Hitable + Sync + Send>,
+}
+
+impl Csg {
+    pub fn new(
+        op: CsgOp,
+        left: Arc<dyn Hitable + Sync + Send>,
+        right: Arc<dyn Hitable + Sync + Send>,
+    ) -> Self {
+        Self { op, left, right }
+    }
+    pub fn union(
+        left: Arc<dyn Hitable + Sync + Send>,
+        right: Arc<dyn Hitable + Sync + Send>,
+    ) -> Self {
+        Self::new(CsgOp::Union, left, right)
+    }
+    pub fn intersection(
+        left: Arc<dyn Hitable + Sync + Send>,
+        right: Arc<dyn Hitable + Sync + Send>,
+    ) -> Self {
+        Self::new(CsgOp::Intersection, left, right)
+    }
+    pub fn difference(
+        left: Arc<dyn Hitable + Sync + Send>,
+        right: Arc<dyn Hitable + Sync + Send>,
+    ) -> Self {
+        Self::new(CsgOp::Difference, left, right)
+    }
+
+    /// The children are intersected from 't_min' to the end of the ray,
+    /// whether the ray starts inside one of them, as happens to rays
+    /// refracted by glass, is told by its first hit being an exit.
+    /// The normal of every hit already faces the ray, only the front
+    /// face has to change, e.g. entering the carving object of a
+    /// difference means leaving the result. With 'first_only' the walk
+    /// stops at the first boundary of the result
+    fn boundaries(&self, r: &Ray, t_min: f32, t_max: f32, first_only: bool) -> Vec<HitRecord> {
+        let left = self.left.hit_all(r, t_min, f32::MAX);
+        let right = self.right.hit_all(r, t_min, f32::MAX);
+        let mut in_left = left.first().is_some_and(|rec| !rec.get_front_face());
+        let mut in_right = right.first().is_some_and(|rec| !rec.get_front_face());
+        let mut inside = self.op.inside(in_left, in_right);
+        let mut hits = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < left.len() || j < right.len() {
+            let from_left =
+                j >= right.len() || (i < left.len() && left[i].get_t() <= right[j].get_t());
+            let rec = if from_left {
+                in_left = left[i].get_front_face();
+                i += 1;
+                &left[i - 1]
+            } else {
+                in_right = right[j].get_front_face();
+                j += 1;
+                &right[j - 1]
+            };
+            if rec.get_t() >= t_max {
+                break;
+            }
+            let now_inside = self.op.inside(in_left, in_right);
+            if now_inside != inside {
+                inside = now_inside;
+                let mut rec = rec.clone();
+                rec.set_front_face(inside);
+                hits.push(rec);
+                if first_only {
+                    break;
+                }
+            }
+        }
+        hits
+    }
+}
+
+impl Hitable for Csg {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        match self.boundaries(r, t_min, t_max, true).into_iter().next() {
+            Some(first) => {
+                *rec = first;
+                true
+            }
+            None => false,
+        }
+    }
+    fn hit_all(&self, r: &Ray, t_min: f32, t_max: f32) -> Vec<HitRecord> {
+        self.boundaries(r, t_min, t_max, false)
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        match self.op {
+            CsgOp::Union => Some(self.left.bounding_box()?.union(&self.right.bounding_box()?)),
+            CsgOp::Intersection => match (self.left.bounding_box(), self.right.bounding_box()) {
+                (Some(l), Some(r)) => l.intersection(&r).or(Some(l)),
+                (l, r) => l.or(r),
+            },
+            CsgOp::Difference => self.left.bounding_box(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+    use crate::vecmath::Vec3;
+
+    // Two unit spheres overlapping between x = 0 and x = 1
+    fn spheres(op: CsgOp) -> Csg {
+        let material = Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+        Csg::new(
+            op,
+            Arc::new(Sphere::new(Vec3::new(0., 0., 0.), 1., material.clone())),
+            Arc::new(Sphere::new(Vec3::new(1., 0., 0.), 1., material)),
+        )
+    }
+
+    // Where the ray enters (true) or leaves (false) the result, checking
+    // that the outward normal agrees with it
+    fn walk(csg: &Csg, origin: Vec3, direction: Vec3) -> Vec<(f32, bool)> {
+        let r = Ray::new(origin, direction);
+        csg.hit_all(&r, 0.001, f32::MAX)
+            .iter()
+            .map(|rec| {
+                let entering = rec.get_front_face();
+                assert_eq!(rec.get_outward_normal().dot(direction) < 0., entering);
+                (rec.get_t(), entering)
+            })
+            .collect()
+    }
+
+    fn assert_walk(hits: &[(f32, bool)], expected: &[(f32, bool)]) {
+        assert_eq!(hits.len(), expected.len(), "{:?}", hits);
+        for (hit, e) in hits.iter().zip(expected) {
+            assert!(
+                (hit.0 - e.0).abs() < 1e-4 && hit.1 == e.1,
+                "{:?} != {:?}",
+                hits,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn intervals_of_overlapping_spheres() {
+        let origin = Vec3::new(-5., 0., 0.);
+        let direction = Vec3::new(1., 0., 0.);
+        let union = walk(&spheres(CsgOp::Union), origin, direction);
+        assert_walk(&union, &[(4., true), (7., false)]);
+        let intersection = walk(&spheres(CsgOp::Intersection), origin, direction);
+        assert_walk(&intersection, &[(5., true), (6., false)]);
+        let difference = walk(&spheres(CsgOp::Difference), origin, direction);
+        assert_walk(&difference, &[(4., true), (5., false)]);
+    }
+
+    #[test]
+    fn ray_starting_inside_an_operand() {
+        // Inside the left sphere only
+        let origin = Vec3::new(-0.5, 0., 0.);
+        let direction = Vec3::new(1., 0., 0.);
+        let union = walk(&spheres(CsgOp::Union), origin, direction);
+        assert_walk(&union, &[(2.5, false)]);
+        let intersection = walk(&spheres(CsgOp::Intersection), origin, direction);
+        assert_walk(&intersection, &[(0.5, true), (1.5, false)]);
+        let difference = walk(&spheres(CsgOp::Difference), origin, direction);
+        assert_walk(&difference, &[(0.5, false)]);
+
+        let csg = spheres(CsgOp::Difference);
+        let mut rec = HitRecord::empty();
+        assert!(csg.hit(&Ray::new(origin, direction), 0.001, f32::MAX, &mut rec));
+        assert!((rec.get_t() - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn carved_surface_faces_out() {
+        // Coming from the side of the carving sphere, the first surface
+        // of the difference is the inside of that sphere at x = 0
+        let csg = spheres(CsgOp::Difference);
+        let r = Ray::new(Vec3::new(5., 0., 0.), Vec3::new(-1., 0., 0.));
+        let mut rec = HitRecord::empty();
+        assert!(csg.hit(&r, 0.001, f32::MAX, &mut rec));
+        assert!((rec.get_t() - 5.).abs() < 1e-4);
+        assert!(rec.get_front_face());
+        let n = rec.get_outward_normal();
+        assert!((n - Vec3::new(1., 0., 0.)).length() < 1e-4);
+        assert!((rec.get_normal() - n).length() < 1e-4);
+
+        // Seen from the side of the solid part it points out of it as well
+        let hits = csg.hit_all(&r, 0.001, f32::MAX);
+        assert_eq!(hits.len(), 2);
+        let n = hits[1].get_outward_normal();
+        assert!((n - Vec3::new(-1., 0., 0.)).length() < 1e-4);
+    }
+}
@@ -0,0 +1,146 @@
+/// A function over [0,1] made of equal steps, with 'func' the value of
+/// each of them, sampled in proportion to the value. The values should
+/// not be negative
+pub struct Distribution1D {
+    func: Vec<f32>,
+    // cdf[i] is the integral up to the start of step i, over the total
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Self {
+        assert!(!func.is_empty(), "A Distribution1D needs at least one step");
+        let n = func.len() as f32;
+        let func: Vec<f32> = func.into_iter().map(|f| f.max(0.)).collect();
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.);
+        let mut sum = 0.;
+        for f in func.iter() {
+            sum += f / n;
+            cdf.push(sum);
+        }
+        let integral = sum;
+        for (i, c) in cdf.iter_mut().enumerate() {
+            // With nothing to follow every step is as likely
+            *c = if integral > 0. {
+                *c / integral
+            } else {
+                i as f32 / n
+            };
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+    /// The average of the function
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+    /// A point in [0,1) picked with 'u' in [0,1), its density and the
+    /// step it is in
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let n = self.func.len();
+        // The last step starting at or before u
+        let i = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(n - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0. {
+            (u - self.cdf[i]) / width
+        } else {
+            0.
+        };
+        let x = ((i as f32 + offset.clamp(0., 1.)) / n as f32).min(1. - f32::EPSILON);
+        (x, self.density(i), i)
+    }
+    /// The density of sample() picking 'x'
+    pub fn pdf(&self, x: f32) -> f32 {
+        self.density(self.step(x))
+    }
+
+    fn step(&self, x: f32) -> usize {
+        ((x * self.func.len() as f32).max(0.) as usize).min(self.func.len() - 1)
+    }
+    fn density(&self, i: usize) -> f32 {
+        if self.integral > 0. {
+            self.func[i] / self.integral
+        } else {
+            1.
+        }
+    }
+}
+
+/// A function over the unit square made of a grid of constant cells,
+/// given row by row. A row is picked first in proportion to its sum
+/// then a cell in it, which is how PBRT samples environment maps
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(width: usize, height: usize, values: &[f32]) -> Self {
+        assert!(
+            width > 0 && height > 0 && values.len() == width * height,
+            "A Distribution2D needs width * height values"
+        );
+        let rows: Vec<Distribution1D> = values
+            .chunks(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral()).collect());
+        Self { rows, marginal }
+    }
+    /// A point (u along the rows, v across them) picked with 'u1' and
+    /// 'u2' in [0,1), and its density over the square
+    pub fn sample(&self, u1: f32, u2: f32) -> (f32, f32, f32) {
+        let (v, pdf_v, row) = self.marginal.sample(u2);
+        let (u, pdf_u, _) = self.rows[row].sample(u1);
+        (u, v, pdf_u * pdf_v)
+    }
+    /// The density of sample() picking (u, v)
+    pub fn pdf(&self, u: f32, v: f32) -> f32 {
+        let row = self.marginal.step(v);
+        self.rows[row].pdf(u) * self.marginal.density(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_have_the_density_of_pdf() {
+        let d = Distribution1D::new(vec![1., 0., 3., 0.5, 2.]);
+        for i in 0..100 {
+            let (x, pdf, _) = d.sample((i as f32 + 0.5) / 100.);
+            assert!(d.func[d.step(x)] > 0., "picked an empty step at {}", x);
+            assert!((pdf - d.pdf(x)).abs() < 1e-5);
+        }
+        let n = 1000;
+        let total: f32 = (0..n).map(|i| d.pdf((i as f32 + 0.5) / n as f32) / n as f32).sum();
+        assert!((total - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn grid_samples_have_the_density_of_pdf() {
+        let values: Vec<f32> = (0..12).map(|i| (i * 7 % 5) as f32).collect();
+        let d = Distribution2D::new(4, 3, &values);
+        for i in 0..20 {
+            for j in 0..20 {
+                let (u, v, pdf) = d.sample((i as f32 + 0.5) / 20., (j as f32 + 0.5) / 20.);
+                assert!(pdf > 0.);
+                assert!((pdf - d.pdf(u, v)).abs() < 1e-4 * pdf);
+            }
+        }
+        let n = 200;
+        let mut total = 0.;
+        for i in 0..n {
+            for j in 0..n {
+                total += d.pdf((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+            }
+        }
+        assert!((total / (n * n) as f32 - 1.).abs() < 1e-3);
+    }
+}
@@ -1,16 +1,42 @@
+use crate::csg::*;
+use crate::lights::*;
 use crate::materials::*;
 use crate::mylib::*;
+use crate::planar::*;
+use crate::principled::*;
+use crate::quadrics::*;
+use crate::sdf::*;
+use crate::sky::*;
+use crate::textures::*;
 use crate::vecmath::*;
+use crate::volumes::*;
 use rand::Rng;
 use std::sync::Arc;
 
 /// A struct to store important data of the intersection of the rays
+#[derive(Clone)]
 pub struct HitRecord {
     t: f32,
     p: Vec3,
+    // The shading normal, which materials use, facing the ray
     normal: Vec3,
+    // The normal of the actual surface, also facing the ray. The two
+    // are the same unless a material changes the shading normal
+    geometric_normal: Vec3,
     front_face: bool,
+    // How the point moves along the surface as u and v change, zero
+    // when the shape does not give them
+    dpdu: Vec3,
+    dpdv: Vec3,
+    // The area of the surface covered by the pixel the ray came from
+    footprint: UvFootprint,
+    // Surface coordinates used to look up textures
+    u: f32,
+    v: f32,
     material: Arc<dyn Material + Sync + Send>,
+    // Set once the opacity was tested, so that lists inside lists do
+    // not roll the dice twice for the same hit
+    alpha_tested: bool,
 }
 
 impl HitRecord {
@@ -19,17 +45,81 @@ impl HitRecord {
             t,
             p,
             normal,
+            geometric_normal: normal,
             material,
             front_face: true,
+            dpdu: Vec3::new(0., 0., 0.),
+            dpdv: Vec3::new(0., 0., 0.),
+            footprint: UvFootprint::default(),
+            u: 0.,
+            v: 0.,
+            alpha_tested: false,
         }
     }
+    /// A record to be filled by Hitable::hit
+    pub fn empty() -> Self {
+        Self::new(
+            0.,
+            Vec3::new(0., 0., 0.),
+            Vec3::new(0., 0., 0.),
+            Arc::new(Lambertian::new(Vec3::new(0., 0., 0.))),
+        )
+    }
     pub fn set_face_normal(&mut self,r: &Ray,outward_normal: &Vec3){
         self.front_face = r.get_direction().dot(*outward_normal) < 0.;
-        self.normal = if self.front_face {*outward_normal} else {*outward_normal * -1.};
+        self.normal = if self.front_face {*outward_normal} else {-*outward_normal};
+        self.geometric_normal = self.normal;
+    }
+    /// Replaces the normal used for shading, 'normal' must be a unit
+    /// vector facing the same side as the geometric one. What is the
+    /// front face is still decided by the geometry
+    pub fn set_shading_normal(&mut self, normal: Vec3) {
+        self.normal = normal;
     }
     pub fn scatter(&self, r_in: &Ray, attenuation: &mut Vec3, scattered: &mut Ray) -> bool {
         self.material.scatter(r_in, self, attenuation, scattered)
     }
+    pub fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        wavelengths: Vec3,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.material
+            .scatter_spectral(r_in, self, wavelengths, attenuation, scattered)
+    }
+    pub fn scatter_lobe(
+        &self,
+        r_in: &Ray,
+        wavelengths: Option<Vec3>,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> Option<Lobe> {
+        self.material
+            .scatter_lobe(r_in, self, wavelengths, attenuation, scattered)
+    }
+    pub fn emitted(&self) -> Vec3 {
+        self.material.emitted(self)
+    }
+    /// The material's eval(), or eval_spectral() when given the
+    /// wavelengths
+    pub fn eval(&self, r_in: &Ray, wi: Vec3, wavelengths: Option<Vec3>) -> Vec3 {
+        match wavelengths {
+            Some(lambda) => self.material.eval_spectral(r_in, self, wi, lambda),
+            None => self.material.eval(r_in, self, wi),
+        }
+    }
+    pub fn pdf(&self, r_in: &Ray, wi: Vec3) -> f32 {
+        self.material.pdf(r_in, self, wi)
+    }
+    /// Whether the ray stops here or goes through a hole in the
+    /// material's opacity. Where it is only partly transparent the
+    /// ray stops with a probability equal to the opacity
+    pub fn is_opaque(&self) -> bool {
+        let alpha = self.material.opacity(self);
+        alpha >= 1. || (alpha > 0. && rand::thread_rng().gen::<f32>() < alpha)
+    }
     pub fn get_t(&self) -> f32 {
         self.t
     }
@@ -39,41 +129,303 @@ impl HitRecord {
     pub fn get_normal(&self) -> Vec3 {
         self.normal
     }
+    pub fn get_geometric_normal(&self) -> Vec3 {
+        self.geometric_normal
+    }
+    /// The geometric normal pointing out of the object, whichever side
+    /// the ray came from
+    pub fn get_outward_normal(&self) -> Vec3 {
+        if self.front_face {
+            self.geometric_normal
+        } else {
+            -self.geometric_normal
+        }
+    }
     pub fn get_front_face(&self) -> bool {
         self.front_face
     }
+    /// The derivatives of the point along u and v. Shapes which do not
+    /// give them, or points where they vanish like the poles of a
+    /// sphere, get two unit vectors perpendicular to the normal
+    pub fn get_tangents(&self) -> (Vec3, Vec3) {
+        if self.dpdu.cross(self.dpdv).squared_len() > 1e-12 {
+            (self.dpdu, self.dpdv)
+        } else {
+            self.get_outward_normal().orthonormal_basis()
+        }
+    }
+    pub fn set_tangents(&mut self, dpdu: Vec3, dpdv: Vec3) {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+    }
+    /// The value of 'texture' here, filtered over the footprint
+    pub fn lookup(&self, texture: &dyn Texture) -> Vec3 {
+        texture.filtered(self.u, self.v, &self.p, &self.footprint)
+    }
+    /// Finds the footprint from the differentials of the ray, by
+    /// hitting the plane tangent to the surface with the rays of the
+    /// next pixels (Igehy 1999). How far their hits are from this one
+    /// along dpdu and dpdv gives the changes of u and v
+    fn set_footprint(&mut self, r: &Ray) {
+        self.footprint = UvFootprint::default();
+        let d = match r.get_differentials() {
+            Some(d) => d,
+            None => return,
+        };
+        let n = self.geometric_normal;
+        let (dpdu, dpdv) = self.get_tangents();
+        let offset = |origin: Vec3, direction: Vec3| {
+            let t = n.dot(self.p - origin) / n.dot(direction);
+            if t.is_finite() {
+                Some(origin + direction * t - self.p)
+            } else {
+                None
+            }
+        };
+        let (dpdx, dpdy) = match (
+            offset(d.rx_origin, d.rx_direction),
+            offset(d.ry_origin, d.ry_direction),
+        ) {
+            (Some(dpdx), Some(dpdy)) => (dpdx, dpdy),
+            _ => return,
+        };
+        // Least squares solution of dpdu * du + dpdv * dv = dp
+        let (uu, uv, vv) = (dpdu.dot(dpdu), dpdu.dot(dpdv), dpdv.dot(dpdv));
+        let det = uu * vv - uv * uv;
+        if det.abs() < 1e-12 {
+            return;
+        }
+        let solve = |dp: Vec3| {
+            let (bu, bv) = (dpdu.dot(dp), dpdv.dot(dp));
+            ((vv * bu - uv * bv) / det, (uu * bv - uv * bu) / det)
+        };
+        let (dudx, dvdx) = solve(dpdx);
+        let (dudy, dvdy) = solve(dpdy);
+        self.footprint = UvFootprint {
+            dudx,
+            dvdx,
+            dudy,
+            dvdy,
+        };
+    }
+    pub fn get_u(&self) -> f32 {
+        self.u
+    }
+    pub fn get_v(&self) -> f32 {
+        self.v
+    }
+    /// Marks the hit as entering (true) or leaving the object, the
+    /// normal is left facing the ray
+    pub fn set_front_face(&mut self, front_face: bool) {
+        self.front_face = front_face;
+    }
+    pub fn set_uv(&mut self, u: f32, v: f32) {
+        self.u = u;
+        self.v = v;
+    }
 }
 
 /// A trait implemented by things that can be hit by a ray
 pub trait Hitable {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool;
+    /// All the intersections inside (t_min, t_max) sorted by t, where
+    /// the front face tells if the ray is entering or leaving.
+    /// By default they are found calling hit() again just after the
+    /// previous one, which works for any closed object
+    fn hit_all(&self, r: &Ray, t_min: f32, t_max: f32) -> Vec<HitRecord> {
+        let mut hits = Vec::new();
+        let mut t = t_min;
+        let mut rec = HitRecord::empty();
+        // The limit is only there in case of a shape that keeps
+        // returning the same t
+        while hits.len() < 64 && self.hit(r, t, t_max, &mut rec) {
+            t = rec.t;
+            hits.push(rec.clone());
+        }
+        hits
+    }
+    /// A box containing the whole object, None when it is infinite
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+/// An axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// 'a' and 'b' are two opposite corners of the box
+    pub fn new(a: Vec3, b: Vec3) -> Self {
+        Self {
+            min: Vec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+            max: Vec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+        }
+    }
+    /// The smallest box containing all the points
+    pub fn from_points(points: &[Vec3]) -> Self {
+        points[1..]
+            .iter()
+            .fold(Self::new(points[0], points[0]), |b, p| b.union(&Self::new(*p, *p)))
+    }
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::new(
+            Vec3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            Vec3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        )
+    }
+    /// The part shared by the two boxes, None if they do not touch
+    pub fn intersection(&self, other: &Aabb) -> Option<Self> {
+        let min = Vec3::new(self.min.x.max(other.min.x), self.min.y.max(other.min.y), self.min.z.max(other.min.z));
+        let max = Vec3::new(self.max.x.min(other.max.x), self.max.y.min(other.max.y), self.max.z.min(other.max.z));
+        if min.x > max.x || min.y > max.y || min.z > max.z {
+            None
+        } else {
+            Some(Self { min, max })
+        }
+    }
+    /// Make the box bigger by 'amount' on every side
+    pub fn grow(&self, amount: f32) -> Self {
+        let d = Vec3::new(amount, amount, amount);
+        Self::new(self.min - d, self.max + d)
+    }
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Vec3::new(a.x, a.y, a.z),
+            Vec3::new(b.x, a.y, a.z),
+            Vec3::new(a.x, b.y, a.z),
+            Vec3::new(b.x, b.y, a.z),
+            Vec3::new(a.x, a.y, b.z),
+            Vec3::new(b.x, a.y, b.z),
+            Vec3::new(a.x, b.y, b.z),
+            Vec3::new(b.x, b.y, b.z),
+        ]
+    }
+    /// Where the ray enters and leaves the box, clipped to (t_min, t_max).
+    /// Each axis gives the range of t between its two planes, and the
+    /// ray is inside the box where all three ranges overlap
+    pub fn hit_range(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let o = r.get_origin();
+        let d = r.get_direction();
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for (o, d, min, max) in [
+            (o.x, d.x, self.min.x, self.max.x),
+            (o.y, d.y, self.min.y, self.max.y),
+            (o.z, d.z, self.min.z, self.max.z),
+        ] {
+            let inv_d = 1. / d;
+            let mut near = (min - o) * inv_d;
+            let mut far = (max - o) * inv_d;
+            if inv_d < 0. {
+                std::mem::swap(&mut near, &mut far);
+            }
+            t0 = t0.max(near);
+            t1 = t1.min(far);
+            if t1 <= t0 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
 }
 
+// How many transparent hits on the same object a ray can go through
+const MAX_TRANSPARENT_HITS: usize = 32;
+
 /// A HitableList contains a list of all the objects that can be hit
 pub struct HitableList {
-    pub list: Vec<Box<dyn Hitable + Sync>>,
+    pub list: Vec<Box<dyn Hitable + Sync + Send>>,
+    /// Lights which are not part of the geometry, only used when the
+    /// list is the whole world
+    pub lights: LightTree,
+    /// What is seen past everything, the default is a blend from
+    /// white to light blue
+    pub sky: Option<Box<dyn Sky + Sync + Send>>,
+}
+
+impl Hitable for HitableList {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        self.hit_list(r, t_min, t_max, rec)
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut boxes = self.list.iter().map(|h| h.bounding_box());
+        let first = boxes.next()??;
+        boxes.try_fold(first, |acc, b| Some(acc.union(&b?)))
+    }
 }
 
 impl HitableList {
-    /// Checks wether the ray hit something in the list
+    pub fn new(list: Vec<Box<dyn Hitable + Sync + Send>>) -> Self {
+        Self {
+            list,
+            lights: LightTree::default(),
+            sky: None,
+        }
+    }
+    pub fn with_light(self, light: Box<dyn Light + Sync + Send>) -> Self {
+        self.with_lights(vec![light])
+    }
+    /// Adds many lights at once, the LightTree is only built once
+    pub fn with_lights(mut self, lights: Vec<Box<dyn Light + Sync + Send>>) -> Self {
+        self.lights.extend(lights);
+        self
+    }
+    pub fn with_sky(mut self, sky: Box<dyn Sky + Sync + Send>) -> Self {
+        self.sky = Some(sky);
+        self
+    }
+    /// Checks wether the ray hit something in the list, keeping the
+    /// closest hit and skipping the transparent parts of materials with
+    /// an opacity mask. Everything looking for what a ray hits first,
+    /// shadow rays included, should come through here
     pub fn hit_list(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
         let mut hit_anything = false;
         let mut closest_so_far = t_max;
+        // Hits are written here first, so a transparent one does not
+        // overwrite the closest opaque hit found so far
+        let mut temp = rec.clone();
         for i in self.list.iter() {
-            if i.hit(r, t_min, closest_so_far, rec) {
-                hit_anything = true;
-                closest_so_far = rec.t;
+            let mut t_start = t_min;
+            // When the ray goes through, the same object may still be
+            // hit further away, like the back of a sphere
+            for _ in 0..MAX_TRANSPARENT_HITS {
+                temp.alpha_tested = false;
+                if !i.hit(r, t_start, closest_so_far, &mut temp) {
+                    break;
+                }
+                if !temp.alpha_tested {
+                    // The opacity mask is filtered over the footprint
+                    temp.set_footprint(r);
+                }
+                if temp.alpha_tested || temp.is_opaque() {
+                    temp.alpha_tested = true;
+                    hit_anything = true;
+                    closest_so_far = temp.t;
+                    std::mem::swap(rec, &mut temp);
+                    break;
+                }
+                t_start = temp.t;
             }
         }
+        // Again for hits from lists under a Transform, found with the
+        // ray in their own space
+        if hit_anything {
+            rec.set_footprint(r);
+        }
         return hit_anything;
     }
 
     pub fn random_scene() -> Self {
         let n = 500;
-        let mut list: Vec<Box<dyn Hitable + Sync>> = Vec::with_capacity(n + 1);
-        list.push(Box::new(Sphere::new(
-            Vec3::new(0., -1000., 0.),
-            1000.,
+        let mut list: Vec<Box<dyn Hitable + Sync + Send>> = Vec::with_capacity(n + 1);
+        list.push(Box::new(Plane::new(
+            Vec3::new(0., 0., 0.),
+            Vec3::new(0., 1., 0.),
             Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
         )));
 
@@ -134,7 +486,1315 @@ impl HitableList {
             1.,
             Arc::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.)),
         )));
-        return Self { list };
+        Self::new(list)
+    }
+
+    /// The classic Cornell box, lit only by the quad on the ceiling.
+    /// The walls go on behind the camera and close the box there,
+    /// so no light from the sky gets in
+    pub fn cornell_box() -> Self {
+        let red: Arc<dyn Material + Sync + Send> =
+            Arc::new(Lambertian::new(Vec3::new(0.65, 0.05, 0.05)));
+        let white: Arc<dyn Material + Sync + Send> =
+            Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
+        let green: Arc<dyn Material + Sync + Send> =
+            Arc::new(Lambertian::new(Vec3::new(0.12, 0.45, 0.15)));
+        let light = Arc::new(DiffuseLight::new(Vec3::new(15., 15., 15.)));
+
+        let tall: Arc<dyn Hitable + Sync + Send> = Arc::new(Cuboid::new(
+            Vec3::new(0., 0., 0.),
+            Vec3::new(165., 330., 165.),
+            white.clone(),
+        ));
+        let short: Arc<dyn Hitable + Sync + Send> = Arc::new(Cuboid::new(
+            Vec3::new(0., 0., 0.),
+            Vec3::new(165., 165., 165.),
+            white.clone(),
+        ));
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Quad::yz_rect(0., 555., -801., 555., 555., green)),
+            Box::new(Quad::yz_rect(0., 555., -801., 555., 0., red)),
+            // Facing down, towards the room
+            Box::new(Quad::new(
+                Vec3::new(213., 554., 227.),
+                Vec3::new(130., 0., 0.),
+                Vec3::new(0., 0., 105.),
+                light,
+            )),
+            Box::new(Quad::xz_rect(0., 555., -801., 555., 0., white.clone())),
+            Box::new(Quad::xz_rect(0., 555., -801., 555., 555., white.clone())),
+            Box::new(Quad::xy_rect(0., 555., 0., 555., 555., white.clone())),
+            Box::new(Quad::xy_rect(0., 555., 0., 555., -801., white)),
+            Box::new(Transform::new(
+                tall,
+                Mat4::translation(Vec3::new(265., 0., 295.)) * Mat4::rotation_y(15.),
+            )),
+            Box::new(Transform::new(
+                short,
+                Mat4::translation(Vec3::new(130., 0., 65.)) * Mat4::rotation_y(-18.),
+            )),
+        ];
+        Self::new(list)
+    }
+
+    /// The Cornell box with its two boxes made of smoke instead
+    pub fn cornell_smoke() -> Self {
+        let mut world = Self::cornell_box();
+        // The two boxes are the last objects of the list
+        let short = world.list.pop().unwrap();
+        let tall = world.list.pop().unwrap();
+        world.list.push(Box::new(ConstantMedium::new(
+            Arc::from(tall),
+            0.01,
+            Vec3::new(0., 0., 0.),
+        )));
+        world.list.push(Box::new(ConstantMedium::new(
+            Arc::from(short),
+            0.01,
+            Vec3::new(1., 1., 1.),
+        )));
+        world
+    }
+
+    /// A cloud floating over the ground. The densities are read from
+    /// "cloud.raw" when there is one, see VoxelGrid::load(), otherwise a
+    /// few blobs are put together in its place
+    pub fn cloud_scene() -> Self {
+        let grid = VoxelGrid::load("cloud.raw").unwrap_or_else(|_| {
+            let blobs = [
+                (Vec3::new(0.5, 0.45, 0.5), 0.3),
+                (Vec3::new(0.3, 0.4, 0.45), 0.2),
+                (Vec3::new(0.7, 0.4, 0.55), 0.22),
+                (Vec3::new(0.55, 0.6, 0.4), 0.2),
+            ];
+            VoxelGrid::from_fn(64, 64, 64, |p| {
+                blobs
+                    .iter()
+                    .map(|(c, r)| (1. - (p - *c).length() / r).max(0.))
+                    .sum::<f32>()
+                    .min(1.)
+            })
+        });
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+            )),
+            Box::new(GridMedium::new(
+                Arc::new(grid),
+                Vec3::new(-2., 0.2, -2.),
+                Vec3::new(2., 4.2, 2.),
+                8.,
+                Arc::new(HenyeyGreenstein::new(Vec3::new(0.95, 0.95, 0.95), 0.6)),
+            )),
+        ];
+        Self::new(list)
+    }
+
+    /// Shapes with no ray intersection formula, rendered through their
+    /// distance functions: a Mandelbulb, a sphere melting into a box, a
+    /// twisted bar, a bumpy ball and a row of small rings
+    pub fn sdf_scene() -> Self {
+        let blend = SmoothUnion::new(
+            Arc::new(SdfSphere::new(Vec3::new(0., 0.9, 0.), 0.45)),
+            Arc::new(SdfBox::new(Vec3::new(0., 0.35, 0.), Vec3::new(0.5, 0.35, 0.5), 0.05)),
+            0.3,
+        );
+        let twisted = Twist::new(
+            Arc::new(SdfBox::new(Vec3::new(0., 0.8, 0.), Vec3::new(0.2, 0.8, 0.2), 0.02)),
+            2.,
+        );
+        let bumpy = Displace::new(Arc::new(SdfSphere::new(Vec3::new(0., 0.5, 0.), 0.45)), 0.03, 20.);
+        let row = Repeat::new(
+            Arc::new(SdfTorus::new(Vec3::new(0., 0.06, 0.), 0.15, 0.06)),
+            Vec3::new(0., 0., 0.45),
+            [0, 0, 4],
+        );
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+            )),
+            Box::new(Transform::new(
+                Arc::new(SdfHitable::new(
+                    Arc::new(Mandelbulb::new(8., 8)),
+                    Arc::new(Metal::new(Vec3::new(0.8, 0.6, 0.3), 0.2)),
+                )),
+                Mat4::translation(Vec3::new(0., 1.1, 0.)),
+            )),
+            Box::new(Transform::new(
+                Arc::new(SdfHitable::new(
+                    Arc::new(blend),
+                    Arc::new(Lambertian::new(Vec3::new(0.2, 0.4, 0.8))),
+                )),
+                Mat4::translation(Vec3::new(0., 0., -2.)),
+            )),
+            Box::new(Transform::new(
+                Arc::new(SdfHitable::new(
+                    Arc::new(twisted),
+                    Arc::new(Lambertian::new(Vec3::new(0.8, 0.3, 0.3))),
+                )),
+                Mat4::translation(Vec3::new(0., 0., 2.)),
+            )),
+            Box::new(Transform::new(
+                Arc::new(SdfHitable::new(Arc::new(bumpy), Arc::new(Dieletric::new(1.5)))),
+                Mat4::translation(Vec3::new(1.5, 0., 1.)),
+            )),
+            Box::new(Transform::new(
+                Arc::new(SdfHitable::new(
+                    Arc::new(row),
+                    Arc::new(Lambertian::new(Vec3::new(0.3, 0.7, 0.3))),
+                )),
+                Mat4::translation(Vec3::new(2.5, 0., 0.)),
+            )),
+        ];
+        Self::new(list)
+    }
+
+    /// Gold, copper, aluminium and silver from smooth to rough, and a
+    /// brushed one in front
+    pub fn metals_scene() -> Self {
+        let mut list: Vec<Box<dyn Hitable + Sync + Send>> = vec![Box::new(Plane::new(
+            Vec3::new(0., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+        ))];
+        let metals: [fn(f32) -> Conductor; 4] = [
+            Conductor::gold,
+            Conductor::copper,
+            Conductor::aluminium,
+            Conductor::silver,
+        ];
+        for (i, metal) in metals.iter().enumerate() {
+            for j in 0..4 {
+                list.push(Box::new(Sphere::new(
+                    Vec3::new(-1.5 * i as f32, 0.5, 1.1 * (j as f32 - 1.5)),
+                    0.5,
+                    Arc::new(metal(j as f32 / 3.)),
+                )));
+            }
+        }
+        list.push(Box::new(Sphere::new(
+            Vec3::new(2., 0.7, 0.),
+            0.7,
+            Arc::new(Conductor::anisotropic(
+                Vec3::new(1.657, 0.880, 0.521),
+                Vec3::new(9.224, 6.270, 4.837),
+                0.05,
+                0.5,
+            )),
+        )));
+        Self::new(list)
+    }
+
+    /// Smooth glass next to frosted glass of growing roughness, a ball
+    /// of clear glass etched with a checker pattern, and a thin and a
+    /// thick block of the same green glass
+    pub fn glass_scene() -> Self {
+        let green = Vec3::new(0.4, 0.8, 0.5);
+        let mut list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+            )),
+            // Something colorful to look at through the glass
+            Box::new(Quad::new(
+                Vec3::new(-3., 0., -3.),
+                Vec3::new(0., 0., 6.),
+                Vec3::new(0., 2., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.8, 0.3, 0.1))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.5, -1.8),
+                0.5,
+                Arc::new(Dieletric::new(1.5)),
+            )),
+        ];
+        for (i, roughness) in [0.1, 0.3, 0.6].iter().enumerate() {
+            list.push(Box::new(Sphere::new(
+                Vec3::new(0., 0.5, -0.6 + 1.2 * i as f32),
+                0.5,
+                Arc::new(RoughDielectric::new(1.5, *roughness)),
+            )));
+        }
+        list.push(Box::new(Sphere::new(
+            Vec3::new(1.5, 0.7, 0.),
+            0.7,
+            Arc::new(RoughDielectric::textured(
+                1.5,
+                Arc::new(CheckerTexture::new(
+                    Arc::new(SolidColor::scalar(0.)),
+                    Arc::new(SolidColor::scalar(0.4)),
+                    10.,
+                )),
+            )),
+        )));
+        list.push(Box::new(Cuboid::new(
+            Vec3::new(1.2, 0., 2.4),
+            Vec3::new(1.3, 0.8, 3.2),
+            Arc::new(Dieletric::new(1.5).with_transmittance(green, 0.5)),
+        )));
+        list.push(Box::new(Cuboid::new(
+            Vec3::new(0.5, 0., -3.2),
+            Vec3::new(1.3, 0.8, -2.4),
+            Arc::new(RoughDielectric::new(1.5, 0.05).with_transmittance(green, 0.5)),
+        )));
+        Self::new(list)
+    }
+
+    /// Principled spheres: in the back row a red plastic going from
+    /// smooth to rough, in the front one a gold with a checker of
+    /// roughness, a car paint with clearcoat, velvet with sheen, glass
+    /// through transmission and brushed metal with anisotropy
+    pub fn principled_scene() -> Self {
+        let mut list: Vec<Box<dyn Hitable + Sync + Send>> = vec![Box::new(Plane::new(
+            Vec3::new(0., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Arc::new(Principled::new(Vec3::new(0.5, 0.5, 0.5)).with_roughness(1.)),
+        ))];
+        for i in 0..5 {
+            list.push(Box::new(Sphere::new(
+                Vec3::new(-1.2, 0.5, -2.4 + 1.2 * i as f32),
+                0.5,
+                Arc::new(Principled::new(Vec3::new(0.8, 0.1, 0.1)).with_roughness(i as f32 / 4.)),
+            )));
+        }
+        let front: Vec<Arc<dyn Material + Sync + Send>> = vec![
+            Arc::new(
+                Principled::new(Vec3::new(1., 0.78, 0.34))
+                    .with_metallic(1.)
+                    .with_texture(
+                        PrincipledParameter::Roughness,
+                        Arc::new(CheckerTexture::new(
+                            Arc::new(SolidColor::scalar(0.1)),
+                            Arc::new(SolidColor::scalar(0.5)),
+                            10.,
+                        )),
+                    ),
+            ),
+            Arc::new(
+                Principled::new(Vec3::new(0.05, 0.1, 0.5))
+                    .with_roughness(0.6)
+                    .with_specular_tint(0.5)
+                    .with_clearcoat(1.)
+                    .with_clearcoat_gloss(0.9),
+            ),
+            Arc::new(
+                Principled::new(Vec3::new(0.4, 0.05, 0.3))
+                    .with_texture(
+                        PrincipledParameter::BaseColor,
+                        Arc::new(CheckerTexture::new(
+                            Arc::new(SolidColor::new(Vec3::new(0.4, 0.05, 0.3))),
+                            Arc::new(SolidColor::new(Vec3::new(0.1, 0.05, 0.4))),
+                            8.,
+                        )),
+                    )
+                    .with_roughness(1.)
+                    .with_sheen(1.)
+                    .with_sheen_tint(0.8),
+            ),
+            Arc::new(
+                Principled::new(Vec3::new(0.9, 1., 0.9))
+                    .with_roughness(0.05)
+                    .with_specular(0.6)
+                    .with_transmission(1.),
+            ),
+            Arc::new(
+                Principled::new(Vec3::new(0.9, 0.9, 0.9))
+                    .with_metallic(1.)
+                    .with_roughness(0.4)
+                    .with_anisotropic(1.),
+            ),
+        ];
+        for (i, material) in front.into_iter().enumerate() {
+            list.push(Box::new(Sphere::new(
+                Vec3::new(0.2, 0.5, -2.4 + 1.2 * i as f32),
+                0.5,
+                material,
+            )));
+        }
+        Self::new(list)
+    }
+
+    /// Bars of light seen through a square prism of dense flint and
+    /// balls of other glasses, in spectral mode the edges of the bars
+    /// split in rainbows. The sky is hidden by a black wall
+    pub fn prism_scene() -> Self {
+        let black: Arc<dyn Material + Sync + Send> =
+            Arc::new(Lambertian::new(Vec3::new(0.02, 0.02, 0.02)));
+        let light: Arc<dyn Material + Sync + Send> =
+            Arc::new(DiffuseLight::new(Vec3::new(4., 4., 4.)));
+        let mut list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.3, 0.3, 0.3))),
+            )),
+            Box::new(Plane::new(
+                Vec3::new(-4., 0., 0.),
+                Vec3::new(1., 0., 0.),
+                black,
+            )),
+        ];
+        for i in 0..6 {
+            let y = 0.3 + 0.6 * i as f32;
+            list.push(Box::new(Quad::new(
+                Vec3::new(-3.99, y, -8.),
+                Vec3::new(0., 0.08, 0.),
+                Vec3::new(0., 0., 16.),
+                light.clone(),
+            )));
+        }
+        list.push(Box::new(Transform::new(
+            Arc::new(Cuboid::new(
+                Vec3::new(-0.35, -0.35, -2.),
+                Vec3::new(0.35, 0.35, 2.),
+                Arc::new(Dieletric::sf11()),
+            )),
+            Mat4::translation(Vec3::new(0., 1.6, 0.)) * Mat4::rotation(Vec3::new(0., 0., 1.), 45.),
+        )));
+        let glasses: Vec<Arc<dyn Material + Sync + Send>> = vec![
+            Arc::new(Dieletric::bk7()),
+            // A crown glass through Cauchy's equation
+            Arc::new(Dieletric::cauchy(1.5046, 0.0042)),
+            Arc::new(Dieletric::sf11()),
+        ];
+        for (i, glass) in glasses.into_iter().enumerate() {
+            list.push(Box::new(Sphere::new(
+                Vec3::new(1., 0.5, -1.5 + 1.5 * i as f32),
+                0.5,
+                glass,
+            )));
+        }
+        Self::new(list)
+    }
+
+    /// Coated materials: red and copper car paint, a white base under
+    /// a thick amber varnish, a rough coat over gold, and the bare
+    /// materials behind them
+    pub fn coated_scene() -> Self {
+        let red: Arc<dyn Material + Sync + Send> =
+            Arc::new(Lambertian::new(Vec3::new(0.7, 0.05, 0.05)));
+        let copper: Arc<dyn Material + Sync + Send> = Arc::new(Conductor::copper(0.4));
+        let white: Arc<dyn Material + Sync + Send> =
+            Arc::new(Lambertian::new(Vec3::new(0.9, 0.9, 0.9)));
+        let gold: Arc<dyn Material + Sync + Send> = Arc::new(Conductor::gold(0.1));
+        let amber = Vec3::new(0.9, 0.5, 0.15);
+        let coated: Vec<Arc<dyn Material + Sync + Send>> = vec![
+            Arc::new(Coated::new(red.clone(), 1.5, 0., 0.01)),
+            Arc::new(Coated::new(copper.clone(), 1.5, 0., 0.01)),
+            Arc::new(Coated::new(white.clone(), 1.5, 0.05, 0.2).with_transmittance(amber, 0.1)),
+            Arc::new(Coated::new(gold.clone(), 1.5, 0.3, 0.01)),
+        ];
+        let bare = vec![red, copper, white, gold];
+        let mut list: Vec<Box<dyn Hitable + Sync + Send>> = vec![Box::new(Plane::new(
+            Vec3::new(0., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+        ))];
+        for (i, (coated, bare)) in coated.into_iter().zip(bare).enumerate() {
+            let z = -1.8 + 1.2 * i as f32;
+            list.push(Box::new(Sphere::new(Vec3::new(0.2, 0.5, z), 0.5, coated)));
+            list.push(Box::new(Sphere::new(Vec3::new(-1.2, 0.5, z), 0.5, bare)));
+        }
+        Self::new(list)
+    }
+
+    /// Lambertian and Oren-Nayar balls side by side on concrete, lit
+    /// from the side inside a black room. The rough ones stay bright up
+    /// to their edges like the full moon, the last one has a checker of
+    /// smooth and rough squares
+    pub fn rough_diffuse_scene() -> Self {
+        let clay = Vec3::new(0.8, 0.5, 0.35);
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Sphere::new(
+                Vec3::new(0., 0., 0.),
+                50.,
+                Arc::new(Lambertian::new(Vec3::new(0., 0., 0.))),
+            )),
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(OrenNayar::new(Vec3::new(0.5, 0.5, 0.5), 30.)),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(2., 8., 14.),
+                6.,
+                Arc::new(DiffuseLight::new(Vec3::new(4., 4., 4.))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.7, -1.6),
+                0.7,
+                Arc::new(Lambertian::new(clay)),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.7, 0.),
+                0.7,
+                Arc::new(OrenNayar::new(clay, 60.)),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.7, 1.6),
+                0.7,
+                Arc::new(OrenNayar::textured(
+                    Arc::new(SolidColor::new(clay)),
+                    Arc::new(CheckerTexture::new(
+                        Arc::new(SolidColor::scalar(0.)),
+                        Arc::new(SolidColor::scalar(90.)),
+                        8.,
+                    )),
+                )),
+            )),
+        ];
+        Self::new(list)
+    }
+
+    /// Thin films: soap bubbles thinning towards the top, a puddle
+    /// of water with oil floating on it and a ball of titanium colored
+    /// by its oxide
+    pub fn iridescent_scene() -> Self {
+        let nanometers = |start: f32, end: f32| -> Arc<dyn Texture + Sync + Send> {
+            Arc::new(GradientTexture::new(
+                Vec3::new(start, start, start),
+                Vec3::new(end, end, end),
+            ))
+        };
+        let mut list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., -0.2, 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.05, 0.05, 0.05))),
+            )),
+            Box::new(Cuboid::new(
+                Vec3::new(-3., -0.2, -4.),
+                Vec3::new(3., 0., 4.),
+                Arc::new(Dieletric::new(1.33).with_thin_film(1.47, nanometers(150., 900.))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.7, 1.5),
+                0.7,
+                Arc::new(
+                    Conductor::new(Vec3::new(2.2, 2.2, 2.2), Vec3::new(3., 3., 3.), 0.05)
+                        .with_thin_film(2.4, nanometers(60., 220.)),
+                ),
+            )),
+        ];
+        for &(center, radius) in [
+            (Vec3::new(0.5, 1.2, -0.4), 0.8),
+            (Vec3::new(1.5, 0.7, -1.8), 0.5),
+            (Vec3::new(-1., 2.2, -1.5), 0.6),
+        ]
+        .iter()
+        {
+            list.push(Box::new(Sphere::new(
+                center,
+                radius,
+                Arc::new(Dieletric::new(1.).with_thin_film(1.33, nanometers(900., 250.))),
+            )));
+        }
+        Self::new(list)
+    }
+
+    /// Translucent things lit from behind: a block of marble, a wax
+    /// candle and a skin colored ball, with a plain Lambertian ball to
+    /// compare. Light comes through their thin edges
+    pub fn subsurface_scene() -> Self {
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.4, 0.4, 0.4))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(-8., 6., 0.),
+                3.,
+                Arc::new(DiffuseLight::new(Vec3::new(3., 3., 3.))),
+            )),
+            Box::new(Cuboid::new(
+                Vec3::new(-0.5, 0., -2.4),
+                Vec3::new(0.5, 1., -1.4),
+                Arc::new(Subsurface::from_albedo(
+                    1.5,
+                    Vec3::new(0.99, 0.98, 0.96),
+                    Vec3::new(0.2, 0.15, 0.12),
+                )),
+            )),
+            Box::new(Cylinder::new(
+                0.35,
+                1.4,
+                Arc::new(
+                    Subsurface::new(1.45, Vec3::new(6., 6., 5.5), Vec3::new(0.05, 0.3, 1.2))
+                        .with_anisotropy(0.6),
+                ),
+            )
+            .capped()),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.5, 1.),
+                0.5,
+                Arc::new(Subsurface::from_albedo(
+                    1.4,
+                    Vec3::new(0.95, 0.75, 0.6),
+                    Vec3::new(0.3, 0.12, 0.08),
+                )),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.5, 2.2),
+                0.5,
+                Arc::new(Lambertian::new(Vec3::new(0.9, 0.75, 0.6))),
+            )),
+        ];
+        Self::new(list)
+    }
+
+    /// Mixed materials: a copper ball with checkered paint chipped off,
+    /// a glass ball frosted halfway, dirt getting thicker towards the
+    /// bottom of a silver ball, and a light half covered in soot
+    pub fn mix_scene() -> Self {
+        let paint: Arc<dyn Material + Sync + Send> =
+            Arc::new(Lambertian::new(Vec3::new(0.1, 0.35, 0.15)));
+        let dirt: Arc<dyn Material + Sync + Send> =
+            Arc::new(Lambertian::new(Vec3::new(0.25, 0.18, 0.1)));
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.5, -1.8),
+                0.5,
+                Arc::new(MixMaterial::masked(
+                    Arc::new(Conductor::copper(0.2)),
+                    paint,
+                    Arc::new(CheckerTexture::new(
+                        Arc::new(SolidColor::scalar(0.)),
+                        Arc::new(SolidColor::scalar(1.)),
+                        6.,
+                    )),
+                )),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.5, -0.6),
+                0.5,
+                Arc::new(MixMaterial::new(
+                    Arc::new(Dieletric::new(1.5)),
+                    Arc::new(Metal::new(Vec3::new(0.9, 0.9, 0.9), 0.5)),
+                    0.5,
+                )),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.5, 0.6),
+                0.5,
+                Arc::new(MixMaterial::masked(
+                    Arc::new(Conductor::silver(0.05)),
+                    dirt,
+                    Arc::new(GradientTexture::new(
+                        Vec3::new(1., 1., 1.),
+                        Vec3::new(0., 0., 0.),
+                    )),
+                )),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.5, 1.8),
+                0.5,
+                Arc::new(MixMaterial::new(
+                    Arc::new(DiffuseLight::new(Vec3::new(3., 2.5, 1.5))),
+                    Arc::new(Lambertian::new(Vec3::new(0.05, 0.05, 0.05))),
+                    0.5,
+                )),
+            )),
+        ];
+        Self::new(list)
+    }
+
+    /// Shapes with holes cut by opacity masks: a fence and a ball made
+    /// of checkers, and a curtain fading out towards the top
+    pub fn cutout_scene() -> Self {
+        let holes = |scale: f32| -> Arc<dyn Texture + Sync + Send> {
+            Arc::new(CheckerTexture::new(
+                Arc::new(SolidColor::scalar(1.)),
+                Arc::new(SolidColor::scalar(0.)),
+                scale,
+            ))
+        };
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+            )),
+            Box::new(Quad::new(
+                Vec3::new(1.5, 0., -2.5),
+                Vec3::new(0., 0., 2.),
+                Vec3::new(0., 1.2, 0.),
+                Arc::new(AlphaCutout::new(
+                    Arc::new(Lambertian::new(Vec3::new(0.6, 0.4, 0.2))),
+                    holes(8.),
+                )),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(-0.5, 0.7, -1.5),
+                0.7,
+                Arc::new(AlphaCutout::new(
+                    Arc::new(Lambertian::new(Vec3::new(0.2, 0.4, 0.7))),
+                    holes(10.),
+                )),
+            )),
+            Box::new(Quad::new(
+                Vec3::new(0., 0., 0.5),
+                Vec3::new(0., 0., 2.),
+                Vec3::new(0., 1.8, 0.),
+                Arc::new(AlphaCutout::new(
+                    Arc::new(Lambertian::new(Vec3::new(0.7, 0.15, 0.15))),
+                    Arc::new(GradientTexture::new(
+                        Vec3::new(1., 1., 1.),
+                        Vec3::new(0., 0., 0.),
+                    )),
+                )),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(-1.5, 0.5, 1.5),
+                0.5,
+                Arc::new(Conductor::gold(0.1)),
+            )),
+        ];
+        Self::new(list)
+    }
+
+    /// Flat shapes made to look bumpy: checkers of tilted normals on
+    /// the floor and on a sphere, and a sphere leaning its normals
+    /// along a height ramp
+    pub fn bumpy_scene() -> Self {
+        let tilted = |a: Vec3, b: Vec3, scale: f32| -> Arc<dyn Texture + Sync + Send> {
+            Arc::new(CheckerTexture::new(
+                Arc::new(SolidColor::new(a)),
+                Arc::new(SolidColor::new(b)),
+                scale,
+            ))
+        };
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(BumpMap::from_normal_map(
+                    Arc::new(Metal::new(Vec3::new(0.6, 0.6, 0.6), 0.2)),
+                    tilted(Vec3::new(0.7, 0.5, 0.96), Vec3::new(0.5, 0.7, 0.96), 1.),
+                )),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.7, -1.2),
+                0.7,
+                Arc::new(BumpMap::from_normal_map(
+                    Arc::new(Conductor::gold(0.1)),
+                    tilted(Vec3::new(0.75, 0.5, 0.93), Vec3::new(0.25, 0.5, 0.93), 16.),
+                )),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.7, 1.2),
+                0.7,
+                Arc::new(BumpMap::new(
+                    Arc::new(Lambertian::new(Vec3::new(0.7, 0.3, 0.2))),
+                    Arc::new(GradientTexture::new(
+                        Vec3::new(0., 0., 0.),
+                        Vec3::new(1., 1., 1.),
+                    )),
+                    0.5,
+                )),
+            )),
+        ];
+        Self::new(list)
+    }
+
+    /// Spheres carved out of procedural textures: marble, wood,
+    /// cells, clouds of fbm and turbulence used as bumps
+    pub fn noise_scene() -> Self {
+        let marble = NoiseTexture::new(NoisePattern::Marble, 2.).with_ramp(ColorRamp::new(vec![
+            (0., Vec3::new(0.25, 0.25, 0.3)),
+            (0.3, Vec3::new(0.8, 0.8, 0.8)),
+            (1., Vec3::new(0.95, 0.95, 0.92)),
+        ]));
+        let wood = NoiseTexture::new(NoisePattern::Wood, 6.)
+            .with_seed(1)
+            .with_ramp(ColorRamp::new(vec![
+                (0., Vec3::new(0.45, 0.25, 0.1)),
+                (0.7, Vec3::new(0.6, 0.38, 0.16)),
+                (1., Vec3::new(0.3, 0.15, 0.05)),
+            ]));
+        let cells = NoiseTexture::new(NoisePattern::Worley, 6.).with_ramp(ColorRamp::linear(
+            Vec3::new(0.9, 0.8, 0.2),
+            Vec3::new(0.1, 0.05, 0.),
+        ));
+        let clouds = NoiseTexture::new(NoisePattern::Fbm, 3.).with_ramp(ColorRamp::linear(
+            Vec3::new(0.1, 0.2, 0.6),
+            Vec3::new(0.9, 0.9, 0.95),
+        ));
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::textured(Arc::new(
+                    NoiseTexture::new(NoisePattern::Perlin, 4.).with_ramp(ColorRamp::linear(
+                        Vec3::new(0.3, 0.3, 0.3),
+                        Vec3::new(0.6, 0.6, 0.6),
+                    )),
+                ))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.5, -2.2),
+                0.5,
+                Arc::new(Principled::new(Vec3::new(1., 1., 1.))
+                    .with_texture(PrincipledParameter::BaseColor, Arc::new(marble))
+                    .with_roughness(0.1)),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.5, -1.1),
+                0.5,
+                Arc::new(Lambertian::textured(Arc::new(wood))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.5, 0.),
+                0.5,
+                Arc::new(Lambertian::textured(Arc::new(cells))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.5, 1.1),
+                0.5,
+                Arc::new(Lambertian::textured(Arc::new(clouds))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.5, 2.2),
+                0.5,
+                Arc::new(BumpMap::new(
+                    Arc::new(Conductor::copper(0.15)),
+                    Arc::new(NoiseTexture::new(NoisePattern::Turbulence, 4.).with_octaves(4)),
+                    0.05,
+                )),
+            )),
+        ];
+        Self::new(list)
+    }
+
+    /// Image textures: a tiled floor fading to its average far away
+    /// thanks to the MIP map, a pixel art sign and an earth, read from
+    /// "earthmap.jpg" when there is one. Without the pictures they are
+    /// made up from noise
+    pub fn image_scene() -> Self {
+        // Small tiles with dark joints, 4 per unit on the floor
+        let (size, tile) = (64, 16);
+        let tiles = (0..size * size)
+            .map(|i| {
+                let (x, y) = (i % size, i / size);
+                let shade = if (x / tile + y / tile) % 2 == 0 { 0.7 } else { 0.45 };
+                if x % tile == 0 || y % tile == 0 {
+                    Vec3::new(0.05, 0.05, 0.05)
+                } else {
+                    Vec3::new(shade, shade * 0.9, shade * 0.8)
+                }
+            })
+            .collect();
+        let floor = ImageTexture::from_pixels(size, size, tiles).with_wrap(WrapMode::Mirror);
+        let heart = [
+            "........",
+            ".XX..XX.",
+            "XXXXXXXX",
+            "XXXXXXXX",
+            ".XXXXXX.",
+            "..XXXX..",
+            "...XX...",
+            "........",
+        ];
+        let pixels = heart
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|c| match c {
+                'X' => Vec3::new(0.8, 0.05, 0.1),
+                _ => Vec3::new(0.9, 0.9, 0.8),
+            })
+            .collect();
+        let sign = ImageTexture::from_pixels(8, 8, pixels)
+            .with_wrap(WrapMode::Clamp)
+            .with_filter(TextureFilter::Nearest);
+        let earth = ImageTexture::open("earthmap.jpg").unwrap_or_else(|_| {
+            let land = NoiseTexture::new(NoisePattern::Fbm, 3.).with_ramp(ColorRamp::new(vec![
+                (0.5, Vec3::new(0.05, 0.1, 0.4)),
+                (0.52, Vec3::new(0.6, 0.55, 0.3)),
+                (0.6, Vec3::new(0.15, 0.4, 0.1)),
+            ]));
+            let (width, height) = (256, 128);
+            let pixels = (0..width * height)
+                .map(|i| {
+                    let u = (i % width) as f32 / width as f32;
+                    let v = 1. - (i / width) as f32 / height as f32;
+                    let phi = u * 2. * std::f32::consts::PI;
+                    let theta = v * std::f32::consts::PI;
+                    let p = Vec3::new(
+                        theta.sin() * phi.cos(),
+                        -theta.cos(),
+                        theta.sin() * phi.sin(),
+                    );
+                    land.value(u, v, &p)
+                })
+                .collect();
+            ImageTexture::from_pixels(width, height, pixels)
+        })
+        .with_filter(TextureFilter::Bilinear);
+        let normals: Arc<dyn Texture + Sync + Send> =
+            match ImageTexture::open_linear("normalmap.png") {
+                Ok(normals) => Arc::new(normals),
+                Err(_) => Arc::new(
+                    NoiseTexture::new(NoisePattern::Worley, 8.).with_ramp(ColorRamp::linear(
+                        Vec3::new(0.5, 0.5, 1.),
+                        Vec3::new(0.7, 0.6, 0.9),
+                    )),
+                ),
+            };
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::textured(Arc::new(floor))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.7, -1.2),
+                0.7,
+                Arc::new(Lambertian::textured(Arc::new(earth))),
+            )),
+            Box::new(Quad::new(
+                Vec3::new(-0.5, 0.2, 0.4),
+                Vec3::new(0., 0., 1.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::textured(Arc::new(sign))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0.5, 0.5, 2.),
+                0.5,
+                Arc::new(BumpMap::from_normal_map(
+                    Arc::new(Metal::new(Vec3::new(0.8, 0.8, 0.8), 0.05)),
+                    normals,
+                )),
+            )),
+        ];
+        Self::new(list)
+    }
+
+    /// A closed room lit only by lights without geometry: the sun
+    /// coming in through a window in the ceiling, a warm point light
+    /// behind a fence cut by a mask and a spot on a golden sphere
+    pub fn lights_scene() -> Self {
+        let wall: Arc<dyn Material + Sync + Send> =
+            Arc::new(Lambertian::new(Vec3::new(0.6, 0.6, 0.6)));
+        let (x0, x1, z0, z1, top) = (-3., 15., -5., 5., 4.);
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.5, 0.45, 0.4))),
+            )),
+            Box::new(Quad::new(
+                Vec3::new(x0, 0., z0),
+                Vec3::new(0., 0., z1 - z0),
+                Vec3::new(0., top, 0.),
+                wall.clone(),
+            )),
+            Box::new(Quad::new(
+                Vec3::new(x1, 0., z0),
+                Vec3::new(0., 0., z1 - z0),
+                Vec3::new(0., top, 0.),
+                wall.clone(),
+            )),
+            Box::new(Quad::new(
+                Vec3::new(x0, 0., z0),
+                Vec3::new(x1 - x0, 0., 0.),
+                Vec3::new(0., top, 0.),
+                wall.clone(),
+            )),
+            Box::new(Quad::new(
+                Vec3::new(x0, 0., z1),
+                Vec3::new(x1 - x0, 0., 0.),
+                Vec3::new(0., top, 0.),
+                wall.clone(),
+            )),
+            // The ceiling, around a window over -1 < x < 1, -3 < z < -1
+            Box::new(Quad::new(
+                Vec3::new(x0, top, z0),
+                Vec3::new(-1. - x0, 0., 0.),
+                Vec3::new(0., 0., z1 - z0),
+                wall.clone(),
+            )),
+            Box::new(Quad::new(
+                Vec3::new(1., top, z0),
+                Vec3::new(x1 - 1., 0., 0.),
+                Vec3::new(0., 0., z1 - z0),
+                wall.clone(),
+            )),
+            Box::new(Quad::new(
+                Vec3::new(-1., top, z0),
+                Vec3::new(2., 0., 0.),
+                Vec3::new(0., 0., 2.),
+                wall.clone(),
+            )),
+            Box::new(Quad::new(
+                Vec3::new(-1., top, -1.),
+                Vec3::new(2., 0., 0.),
+                Vec3::new(0., 0., z1 + 1.),
+                wall,
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.6, 0.),
+                0.6,
+                Arc::new(OrenNayar::new(Vec3::new(0.8, 0.3, 0.2), 20.)),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.6, 2.2),
+                0.6,
+                Arc::new(Conductor::gold(0.3)),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.6, -2.2),
+                0.6,
+                Arc::new(Principled::new(Vec3::new(0.2, 0.4, 0.8)).with_roughness(0.3)),
+            )),
+            Box::new(Quad::new(
+                Vec3::new(-1.5, 0., -4.),
+                Vec3::new(0., 0., 8.),
+                Vec3::new(0., 1.5, 0.),
+                Arc::new(AlphaCutout::new(
+                    Arc::new(Lambertian::new(Vec3::new(0.4, 0.3, 0.2))),
+                    Arc::new(CheckerTexture::new(
+                        Arc::new(SolidColor::scalar(1.)),
+                        Arc::new(SolidColor::scalar(0.)),
+                        6.,
+                    )),
+                )),
+            )),
+        ];
+        Self::new(list)
+            .with_light(Box::new(DirectionalLight::new(
+                Vec3::new(0.5, -1., 0.3),
+                Vec3::new(3., 2.8, 2.5),
+            )))
+            .with_light(Box::new(PointLight::new(
+                Vec3::new(-2.5, 2., 0.),
+                Vec3::new(4., 2.5, 1.2),
+            )))
+            .with_light(Box::new(
+                SpotLight::new(
+                    Vec3::new(1., 3.5, 2.2),
+                    Vec3::new(-0.3, -1., 0.),
+                    Vec3::new(12., 12., 12.),
+                    25.,
+                )
+                .with_falloff(15.),
+            ))
+    }
+
+    /// A few buildings in the afternoon under a clear sky, the sun
+    /// is a light so the shadows are sharp and clean
+    pub fn daylight_scene() -> Self {
+        // A sun twice as big as the real one, for softer shadows
+        let sky = PreethamSky::new(Vec3::new(-0.6, 0.45, -0.5), 3., Vec3::new(0.3, 0.3, 0.3))
+            .with_intensity(0.04)
+            .with_sun_size(0.5);
+        let sun = sky.sun();
+        let concrete: Arc<dyn Material + Sync + Send> =
+            Arc::new(Lambertian::new(Vec3::new(0.55, 0.53, 0.5)));
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.3, 0.3, 0.3))),
+            )),
+            Box::new(Cuboid::new(
+                Vec3::new(-3., 0., -4.),
+                Vec3::new(-1., 3.5, -1.5),
+                concrete.clone(),
+            )),
+            Box::new(Cuboid::new(
+                Vec3::new(-2.5, 0., 1.),
+                Vec3::new(-0.5, 2., 3.5),
+                Arc::new(Principled::new(Vec3::new(0.7, 0.35, 0.25)).with_roughness(0.6)),
+            )),
+            Box::new(Cuboid::new(
+                Vec3::new(-0.5, 0., -1.),
+                Vec3::new(0.5, 1.2, 0.5),
+                Arc::new(Dieletric::new(1.5).with_transmittance(Vec3::new(0.7, 0.85, 0.9), 1.)),
+            )),
+            Box::new(Cuboid::new(
+                Vec3::new(1., 0., 1.5),
+                Vec3::new(1.6, 0.6, 2.1),
+                concrete,
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(1.5, 0.5, -1.5),
+                0.5,
+                Arc::new(Metal::new(Vec3::new(0.9, 0.9, 0.9), 0.)),
+            )),
+        ];
+        Self::new(list)
+            .with_sky(Box::new(sky))
+            .with_light(Box::new(sun))
+    }
+
+    /// Shiny balls lit only by an environment map, "environment.hdr"
+    /// when there is one or else a made up sky with a small and very
+    /// bright sun, which the shadow rays find through the importance
+    /// sampling of the map
+    pub fn environment_scene() -> Self {
+        let environment = EnvironmentMap::open("environment.hdr").unwrap_or_else(|_| {
+            let (width, height) = (256, 128);
+            let sun = Vec3::new(1., 0.6, 0.).unit_vector();
+            let pixels = (0..width * height)
+                .map(|i| {
+                    let u = ((i % width) as f32 + 0.5) / width as f32;
+                    let v = ((i / width) as f32 + 0.5) / height as f32;
+                    let phi = (u * 2. - 1.) * std::f32::consts::PI;
+                    let theta = v * std::f32::consts::PI;
+                    let d = Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                    if d.dot(sun) > 2_f32.to_radians().cos() {
+                        Vec3::new(900., 800., 650.)
+                    } else if d.y > 0. {
+                        Vec3::new(0.6, 0.75, 1.) * (1. - d.y * 0.6)
+                    } else {
+                        Vec3::new(0.3, 0.25, 0.2)
+                    }
+                })
+                .collect();
+            EnvironmentMap::from_pixels(width, height, pixels)
+        });
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.7, -2.4),
+                0.7,
+                Arc::new(Principled::new(Vec3::new(0.8, 0.1, 0.1)).with_roughness(0.3)),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.7, -0.8),
+                0.7,
+                Arc::new(Conductor::gold(0.2)),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.7, 0.8),
+                0.7,
+                Arc::new(Lambertian::new(Vec3::new(0.8, 0.8, 0.8))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 0.7, 2.4),
+                0.7,
+                Arc::new(RoughDielectric::new(1.5, 0.1)),
+            )),
+        ];
+        Self::new(list).with_sky(Box::new(environment.with_rotation(30.).with_intensity(0.8)))
+    }
+
+    /// A square at night under two thousand small bulbs hanging on
+    /// strings, all of them lights. The LightTree picks the bulbs
+    /// close to each point, looking at all of them would take ages
+    pub fn many_lights_scene() -> Self {
+        let mut rng = rand::thread_rng();
+        let (strings, bulbs) = (40, 50);
+        let mut lights: Vec<Box<dyn Light + Sync + Send>> = Vec::with_capacity(strings * bulbs);
+        for i in 0..strings {
+            let z = -6. + 12. * i as f32 / (strings - 1) as f32;
+            for j in 0..bulbs {
+                let t = j as f32 / (bulbs - 1) as f32;
+                // Sagging between the two poles
+                let y = 3. - 0.8 * (1. - (2. * t - 1.).powi(2));
+                let color = match rng.gen_range(0..4) {
+                    0 => Vec3::new(1., 0.6, 0.2),
+                    1 => Vec3::new(1., 0.2, 0.3),
+                    2 => Vec3::new(0.3, 0.5, 1.),
+                    _ => Vec3::new(1., 0.9, 0.7),
+                };
+                lights.push(Box::new(SphereLight::new(
+                    Vec3::new(-6. + 12. * t, y, z),
+                    0.03,
+                    color * 10.,
+                )));
+            }
+        }
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(0., 1., 0.),
+                1.,
+                Arc::new(Principled::new(Vec3::new(0.1, 0.3, 0.6)).with_roughness(0.2)),
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(-3., 0.7, 2.),
+                0.7,
+                Arc::new(Conductor::silver(0.15)),
+            )),
+            Box::new(Cuboid::new(
+                Vec3::new(1.5, 0., -3.),
+                Vec3::new(3., 1.5, -1.5),
+                Arc::new(Lambertian::new(Vec3::new(0.7, 0.7, 0.7))),
+            )),
+        ];
+        // A dark blue night sky, the same in every direction
+        let night = EnvironmentMap::from_pixels(1, 1, vec![Vec3::new(0.01, 0.012, 0.03)]);
+        Self::new(list).with_lights(lights).with_sky(Box::new(night))
+    }
+
+    /// One of each of the quadrics, some of them cut open
+    pub fn quadrics_scene() -> Self {
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+            )),
+            Box::new(Transform::new(
+                Arc::new(
+                    Cylinder::new(0.6, 1.5, Arc::new(Lambertian::new(Vec3::new(0.8, 0.3, 0.3))))
+                        .with_sweep(300.)
+                        .with_height_limits(0., 1.2)
+                        .capped(),
+                ),
+                Mat4::translation(Vec3::new(0., 0., -2.)),
+            )),
+            Box::new(Transform::new(
+                Arc::new(
+                    Cone::new(0.7, 1.6, Arc::new(Metal::new(Vec3::new(0.8, 0.6, 0.2), 0.1)))
+                        .with_sweep(270.)
+                        .with_height_limits(0., 1.2)
+                        .capped(),
+                ),
+                Mat4::translation(Vec3::new(0., 0., 0.)),
+            )),
+            Box::new(Transform::new(
+                Arc::new(
+                    Paraboloid::new(0.6, 1.2, Arc::new(Lambertian::new(Vec3::new(0.2, 0.4, 0.8))))
+                        .with_sweep(320.)
+                        .with_height_limits(0.3, 1.2)
+                        .capped(),
+                ),
+                Mat4::translation(Vec3::new(0., 0., 2.)),
+            )),
+            Box::new(Transform::new(
+                Arc::new(
+                    Torus::new(0.7, 0.25, Arc::new(Dieletric::new(1.5)))
+                        .with_sweep(300.)
+                        .with_height_limits(-0.25, 0.15),
+                ),
+                Mat4::translation(Vec3::new(2., 0.9, 0.)) * Mat4::rotation_y(30.)
+                    * Mat4::rotation(Vec3::new(1., 0., 0.), 70.),
+            )),
+        ];
+        Self::new(list)
+    }
+
+    /// A lens, a cube with a sphere carved out of it and a drilled
+    /// part made of two spheres, all built with Csg
+    pub fn csg_scene() -> Self {
+        let glass: Arc<dyn Material + Sync + Send> = Arc::new(Dieletric::new(1.5));
+        let red: Arc<dyn Material + Sync + Send> =
+            Arc::new(Lambertian::new(Vec3::new(0.8, 0.2, 0.2)));
+        let steel: Arc<dyn Material + Sync + Send> =
+            Arc::new(Metal::new(Vec3::new(0.7, 0.7, 0.75), 0.2));
+
+        // Biconvex lens, the part shared by two big spheres
+        let lens = Csg::intersection(
+            Arc::new(Sphere::new(Vec3::new(-1.6, 1.3, 0.), 2., glass.clone())),
+            Arc::new(Sphere::new(Vec3::new(1.6, 1.3, 0.), 2., glass)),
+        );
+        let cube = Csg::difference(
+            Arc::new(Cuboid::new(
+                Vec3::new(-0.8, 0., -0.8),
+                Vec3::new(0.8, 1.6, 0.8),
+                red.clone(),
+            )),
+            Arc::new(Sphere::new(Vec3::new(0., 1.6, 0.), 1., red)),
+        );
+        let drill: Arc<dyn Hitable + Sync + Send> = Arc::new(Transform::new(
+            Arc::new(Cylinder::new(0.3, 4., steel.clone()).capped()),
+            Mat4::translation(Vec3::new(-2., 0.6, 0.4)) * Mat4::rotation(Vec3::new(0., 0., 1.), -90.),
+        ));
+        let part = Csg::difference(
+            Arc::new(Csg::union(
+                Arc::new(Sphere::new(Vec3::new(0., 0.6, -0.4), 0.6, steel.clone())),
+                Arc::new(Sphere::new(Vec3::new(0., 0.6, 0.4), 0.6, steel)),
+            )),
+            drill,
+        );
+
+        let list: Vec<Box<dyn Hitable + Sync + Send>> = vec![
+            Box::new(Plane::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+            )),
+            Box::new(lens),
+            Box::new(Transform::new(
+                Arc::new(cube),
+                Mat4::translation(Vec3::new(-1.5, 0., -2.5)) * Mat4::rotation_y(30.),
+            )),
+            Box::new(Transform::new(
+                Arc::new(part),
+                Mat4::translation(Vec3::new(-0.5, 0., 2.5)),
+            )),
+        ];
+        Self::new(list)
+    }
+
+    /// A field of ellipsoids which all share the same sphere, every
+    /// instance only stores its own transform and material
+    pub fn instances_scene() -> Self {
+        let n = 30;
+        let mut list: Vec<Box<dyn Hitable + Sync + Send>> = Vec::with_capacity(n * n + 1);
+        // A round platform floating in the sky
+        list.push(Box::new(Disk::new(
+            Vec3::new(0., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            0.5 * n as f32,
+            Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+        )));
+
+        let shape: Arc<dyn Hitable + Sync + Send> = Arc::new(Sphere::new(
+            Vec3::new(0., 0., 0.),
+            1.,
+            Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+        ));
+        let mut rng = rand::thread_rng();
+        for a in 0..n {
+            for b in 0..n {
+                let scale = Vec3::new(
+                    0.1 + 0.15 * rng.gen::<f32>(),
+                    0.1 + 0.25 * rng.gen::<f32>(),
+                    0.1 + 0.15 * rng.gen::<f32>(),
+                );
+                let position = Vec3::new(
+                    (a as f32 - n as f32 / 2.) * 0.7,
+                    scale.y,
+                    (b as f32 - n as f32 / 2.) * 0.7,
+                );
+                let matrix = Mat4::translation(position)
+                    * Mat4::rotation_y(360. * rng.gen::<f32>())
+                    * Mat4::scaling(scale);
+                let material: Arc<dyn Material + Sync + Send> = if rng.gen::<f32>() < 0.7 {
+                    Arc::new(Lambertian::new(Vec3::new(rng.gen(), rng.gen(), rng.gen())))
+                } else {
+                    Arc::new(Metal::new(
+                        Vec3::new(rng.gen(), rng.gen(), rng.gen()),
+                        0.3 * rng.gen::<f32>(),
+                    ))
+                };
+                list.push(Box::new(
+                    Transform::new(shape.clone(), matrix).with_material(material),
+                ));
+            }
+        }
+        Self::new(list)
     }
 }
 
@@ -154,6 +1814,30 @@ impl Sphere {
     }
 }
 
+// Maps a point of the unit sphere to u = longitude and v = latitude,
+// both in [0,1], v goes from the bottom pole to the top one
+fn sphere_uv(p: &Vec3) -> (f32, f32) {
+    let theta = (-p.y).acos();
+    let phi = (-p.z).atan2(p.x) + std::f32::consts::PI;
+    (
+        phi / (2. * std::f32::consts::PI),
+        theta / std::f32::consts::PI,
+    )
+}
+
+// The derivatives along u and v of the point 'center + radius * n',
+// following sphere_uv(). Both vanish at the poles
+fn sphere_tangents(n: &Vec3, radius: f32) -> (Vec3, Vec3) {
+    let pi = std::f32::consts::PI;
+    let rho = (n.x * n.x + n.z * n.z).sqrt();
+    let dpdu = Vec3::new(n.z, 0., -n.x) * (2. * pi * radius);
+    if rho < 1e-6 {
+        return (dpdu, Vec3::new(0., 0., 0.));
+    }
+    let dpdv = Vec3::new(-n.y * n.x / rho, rho, -n.y * n.z / rho) * (pi * radius);
+    (dpdu, dpdv)
+}
+
 impl Hitable for Sphere {
     /// Returns true if the ray hit the sphere and if it does, sets
     /// t_min as the closest value to the origin, so we will see
@@ -171,8 +1855,13 @@ impl Hitable for Sphere {
             if temp < t_max && temp > t_min {
                 rec.t = temp;
                 rec.p = r.point_at_parameter(temp);
-                rec.set_face_normal(r,&((rec.p - self.center) / self.radius));
+                let outward_normal = (rec.p - self.center) / self.radius;
+                rec.set_face_normal(r,&outward_normal);
                 // rec.normal = (rec.p - self.center) / self.radius;
+                let (u, v) = sphere_uv(&outward_normal);
+                rec.set_uv(u, v);
+                let (dpdu, dpdv) = sphere_tangents(&outward_normal, self.radius);
+                rec.set_tangents(dpdu, dpdv);
                 rec.material = self.material.clone();
                 return true;
             }
@@ -180,7 +1869,12 @@ impl Hitable for Sphere {
             if temp < t_max && temp > t_min {
                 rec.t = temp;
                 rec.p = r.point_at_parameter(temp);
-                rec.normal = (rec.p - self.center) / self.radius;
+                let outward_normal = (rec.p - self.center) / self.radius;
+                rec.set_face_normal(r,&outward_normal);
+                let (u, v) = sphere_uv(&outward_normal);
+                rec.set_uv(u, v);
+                let (dpdu, dpdv) = sphere_tangents(&outward_normal, self.radius);
+                rec.set_tangents(dpdu, dpdv);
                 rec.material = self.material.clone();
                 return true;
             }
@@ -189,4 +1883,116 @@ impl Hitable for Sphere {
             return false;
         }
     }
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius.abs();
+        Some(Aabb::new(
+            self.center - Vec3::new(r, r, r),
+            self.center + Vec3::new(r, r, r),
+        ))
+    }
+}
+
+/// Places another hitable in the world through an affine transform.
+/// The object is shared through an Arc, so the same geometry can be
+/// instanced many times while only paying for the matrices
+pub struct Transform {
+    object: Arc<dyn Hitable + Sync + Send>,
+    matrix: Mat4,
+    inverse: Mat4,
+    // Normals need the inverse transpose to stay perpendicular
+    // to the surface when the scale is not uniform
+    normal_matrix: Mat4,
+    material: Option<Arc<dyn Material + Sync + Send>>,
+}
+
+impl Transform {
+    pub fn new(object: Arc<dyn Hitable + Sync + Send>, matrix: Mat4) -> Self {
+        let inverse = matrix
+            .inverse()
+            .expect("The matrix of a Transform must be invertible");
+        Self {
+            object,
+            matrix,
+            inverse,
+            normal_matrix: inverse.transpose(),
+            material: None,
+        }
+    }
+    /// Use 'material' instead of the one of the wrapped object
+    pub fn with_material(mut self, material: Arc<dyn Material + Sync + Send>) -> Self {
+        self.material = Some(material);
+        self
+    }
+}
+
+impl Hitable for Transform {
+    /// The ray is moved into object space, the direction is not
+    /// normalized so the t found there is valid in world space too
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let local = Ray::new(
+            self.inverse.transform_point(r.get_origin()),
+            self.inverse.transform_vector(r.get_direction()),
+        );
+        if !self.object.hit(&local, t_min, t_max, rec) {
+            return false;
+        }
+        rec.p = r.point_at_parameter(rec.t);
+        rec.normal = self.normal_matrix.transform_vector(rec.normal).unit_vector();
+        rec.geometric_normal = self
+            .normal_matrix
+            .transform_vector(rec.geometric_normal)
+            .unit_vector();
+        rec.dpdu = self.matrix.transform_vector(rec.dpdu);
+        rec.dpdv = self.matrix.transform_vector(rec.dpdv);
+        if let Some(material) = &self.material {
+            rec.material = material.clone();
+        }
+        true
+    }
+    /// The box around the eight transformed corners of the object's box
+    fn bounding_box(&self) -> Option<Aabb> {
+        let b = self.object.bounding_box()?;
+        let corners = b.corners().map(|c| self.matrix.transform_point(c));
+        Some(Aabb::from_points(&corners))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transformed_normal_stays_perpendicular() {
+        let sphere = Sphere::new(
+            Vec3::new(0., 0., 0.),
+            1.,
+            Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+        );
+        // Stretched on x, then turned, so the scale is not uniform
+        let matrix =
+            Mat4::rotation(Vec3::new(0., 0., 1.), 30.) * Mat4::scaling(Vec3::new(3., 1., 0.5));
+        let ellipsoid = Transform::new(Arc::new(sphere), matrix);
+        let inverse = matrix.inverse().unwrap();
+        for origin in [
+            Vec3::new(5., 1., 2.),
+            Vec3::new(-1., 4., -3.),
+            Vec3::new(0.5, -0.3, 6.),
+        ] {
+            let r = Ray::new(origin, Vec3::new(0.1, 0.2, 0.) - origin);
+            let mut rec = HitRecord::new(
+                0.,
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 0., 0.),
+                Arc::new(Lambertian::new(Vec3::new(0., 0., 0.))),
+            );
+            assert!(ellipsoid.hit(&r, 0.001, f32::MAX, &mut rec));
+            // On the surface of the ellipsoid
+            assert!((inverse.transform_point(rec.get_p()).length() - 1.).abs() < 1e-4);
+            let n = rec.get_normal();
+            assert!((n.length() - 1.).abs() < 1e-4);
+            let (dpdu, dpdv) = rec.get_tangents();
+            assert!(n.dot(dpdu.unit_vector()).abs() < 1e-4);
+            assert!(n.dot(dpdv.unit_vector()).abs() < 1e-4);
+        }
+    }
 }
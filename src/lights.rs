@@ -0,0 +1,658 @@
+use crate::hitables::Aabb;
+use crate::mylib::Ray;
+use crate::vecmath::{Mat4, Vec3};
+use rand::Rng;
+use std::f32::consts::PI;
+
+/// The light arriving at a point from one light, which is seen if
+/// nothing is in the way of the shadow ray
+pub struct LightSample {
+    /// Unit vector from the point towards the light
+    pub direction: Vec3,
+    /// How far the light is along 'direction', the shadow ray stops
+    /// there
+    pub distance: f32,
+    /// The light arriving, as the irradiance on a surface facing it
+    pub radiance: Vec3,
+    /// The density of picking 'direction', per unit of solid angle.
+    /// Infinite for points and single directions, which can only be
+    /// found with shadow rays
+    pub pdf: f32,
+}
+
+/// Lights which are not part of the geometry. They are found by
+/// looking for them with a shadow ray from every hit, most of them
+/// being so small that no ray could hit them by chance. So points,
+/// spots and directions only light the materials with an eval():
+/// a Metal without fuzziness, smooth Dieletric and Subsurface stay
+/// black under them and do not show them in reflections
+pub trait Light {
+    /// The light reaching 'p', None when it cannot reach it at all.
+    /// Lights with a size pick a point on them at random, the radiance
+    /// is then divided by the density of picking it
+    fn sample(&self, p: Vec3) -> Option<LightSample>;
+    /// What a ray leaving the scene along 'direction' sees of the
+    /// light, only for lights far away with a size, like the sun.
+    /// Points and single directions can never be seen
+    fn emitted(&self, _direction: Vec3) -> Vec3 {
+        Vec3::new(0., 0., 0.)
+    }
+    /// The density of sample() picking 'direction' from 'p', needed by
+    /// every light which can be seen, through emitted() or hit()
+    fn pdf(&self, _p: Vec3, _direction: Vec3) -> f32 {
+        0.
+    }
+    /// Where the ray meets the light and the light it sees there, only
+    /// for lights with a size which are not far away
+    fn hit(&self, _r: &Ray, _t_min: f32, _t_max: f32) -> Option<(f32, Vec3)> {
+        None
+    }
+    /// Where the light is and where it shines, for the LightTree to
+    /// guess how much of it reaches a point. None for lights which are
+    /// everywhere, like the sun, they are then looked for every time
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+fn luminance(c: Vec3) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+// Towards 'position' from 'p', with the light falling off with the
+// square of the distance
+fn towards(position: Vec3, p: Vec3, intensity: Vec3) -> Option<LightSample> {
+    let to_light = position - p;
+    let distance2 = to_light.squared_len();
+    if distance2 == 0. {
+        return None;
+    }
+    let distance = distance2.sqrt();
+    Some(LightSample {
+        direction: to_light / distance,
+        distance,
+        radiance: intensity / distance2,
+        pdf: f32::INFINITY,
+    })
+}
+
+/// A light bulb shrunk to a point, shining the same in every direction
+pub struct PointLight {
+    position: Vec3,
+    intensity: Vec3,
+}
+
+impl PointLight {
+    /// 'intensity' is the power per unit of solid angle, a surface
+    /// facing it at a distance d gets intensity / d^2
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Vec3) -> Option<LightSample> {
+        towards(self.position, p, self.intensity)
+    }
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds::everywhere(
+            Aabb::new(self.position, self.position),
+            4. * PI * luminance(self.intensity),
+        ))
+    }
+}
+
+/// A point light only shining inside a cone, fading out smoothly
+/// towards its border
+pub struct SpotLight {
+    position: Vec3,
+    direction: Vec3,
+    intensity: Vec3,
+    // Cosines of the angle of the cone and of where it starts fading
+    cos_cone: f32,
+    cos_falloff: f32,
+}
+
+impl SpotLight {
+    /// 'cone' is the angle from 'direction' to the border, in degrees.
+    /// The light starts fading at half of it
+    pub fn new(position: Vec3, direction: Vec3, intensity: Vec3, cone: f32) -> Self {
+        Self {
+            position,
+            direction: direction.unit_vector(),
+            intensity,
+            cos_cone: cone.to_radians().cos(),
+            cos_falloff: (cone / 2.).to_radians().cos(),
+        }
+    }
+    /// The angle from 'direction' at which it starts fading, in degrees
+    pub fn with_falloff(mut self, falloff: f32) -> Self {
+        self.cos_falloff = falloff.to_radians().cos().max(self.cos_cone);
+        self
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: Vec3) -> Option<LightSample> {
+        let mut sample = towards(self.position, p, self.intensity)?;
+        let cos = -sample.direction.dot(self.direction);
+        if cos <= self.cos_cone {
+            return None;
+        }
+        if cos < self.cos_falloff {
+            // Smoothstep between the border and the start of the fade
+            let t = (cos - self.cos_cone) / (self.cos_falloff - self.cos_cone);
+            sample.radiance *= t * t * (3. - 2. * t);
+        }
+        Some(sample)
+    }
+    fn bounds(&self) -> Option<LightBounds> {
+        // The fade goes from the edge of the full light to the border
+        let theta_o = self.cos_falloff.acos();
+        let theta_e = self.cos_cone.acos() - theta_o;
+        // The fade counts for about half of its solid angle
+        let solid_angle = 2. * PI * (1. - (self.cos_falloff + self.cos_cone) / 2.);
+        Some(LightBounds {
+            aabb: Aabb::new(self.position, self.position),
+            power: solid_angle * luminance(self.intensity),
+            axis: self.direction,
+            cos_theta_o: self.cos_falloff,
+            cos_theta_e: theta_e.cos(),
+        })
+    }
+}
+
+/// Light coming from very far away along the same direction
+/// everywhere, like the sun
+pub struct DirectionalLight {
+    // Where the light comes from, opposite to where it goes
+    to_light: Vec3,
+    irradiance: Vec3,
+}
+
+impl DirectionalLight {
+    /// 'direction' is where the light goes, 'irradiance' is what a
+    /// surface facing the light gets
+    pub fn new(direction: Vec3, irradiance: Vec3) -> Self {
+        Self {
+            to_light: direction.unit_vector() * -1.,
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: Vec3) -> Option<LightSample> {
+        Some(LightSample {
+            direction: self.to_light,
+            distance: f32::MAX,
+            radiance: self.irradiance,
+            pdf: f32::INFINITY,
+        })
+    }
+}
+
+/// A glowing ball, which gives soft shadows. It is found with shadow
+/// rays like the other lights and can be seen, but it is not part of
+/// the geometry so it casts no shadow itself
+pub struct SphereLight {
+    center: Vec3,
+    radius: f32,
+    radiance: Vec3,
+}
+
+impl SphereLight {
+    /// 'radiance' is how bright its surface looks
+    pub fn new(center: Vec3, radius: f32, radiance: Vec3) -> Self {
+        Self {
+            center,
+            radius,
+            radiance,
+        }
+    }
+    // The unit vector from 'p' to the center, the cosine of the angle
+    // of the cone the ball covers seen from 'p' and 1 minus it, the
+    // last one found without cancellation for balls far away
+    fn cone(&self, p: Vec3) -> Option<(Vec3, f32, f32)> {
+        let to_center = self.center - p;
+        let distance2 = to_center.squared_len();
+        let sin2 = self.radius * self.radius / distance2;
+        if sin2 >= 1. {
+            return None;
+        }
+        let cos_max = (1. - sin2).sqrt();
+        Some((to_center / distance2.sqrt(), cos_max, sin2 / (1. + cos_max)))
+    }
+}
+
+impl Light for SphereLight {
+    /// A direction picked uniformly in the cone the ball covers
+    fn sample(&self, p: Vec3) -> Option<LightSample> {
+        let (axis, _, one_minus_cos) = self.cone(p)?;
+        let mut rng = rand::thread_rng();
+        let cos_theta = 1. - rng.gen::<f32>() * one_minus_cos;
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * rng.gen::<f32>();
+        let (t, b) = axis.orthonormal_basis();
+        let direction =
+            t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + axis * cos_theta;
+        // The closest of the two points of the ball along it
+        let d = (self.center - p).length();
+        let half_chord = (self.radius * self.radius - d * d * sin_theta * sin_theta)
+            .max(0.)
+            .sqrt();
+        let solid_angle = 2. * PI * one_minus_cos;
+        Some(LightSample {
+            direction,
+            distance: d * cos_theta - half_chord,
+            radiance: self.radiance * solid_angle,
+            pdf: 1. / solid_angle,
+        })
+    }
+    fn pdf(&self, p: Vec3, direction: Vec3) -> f32 {
+        match self.cone(p) {
+            Some((axis, cos_max, one_minus_cos)) if direction.dot(axis) >= cos_max => {
+                1. / (2. * PI * one_minus_cos)
+            }
+            _ => 0.,
+        }
+    }
+    /// Only from outside, where it can be sampled
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, Vec3)> {
+        let oc = r.get_origin() - self.center;
+        let d = r.get_direction();
+        let a = d.dot(d);
+        let half_b = oc.dot(d);
+        let c = oc.dot(oc) - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
+        if c <= 0. || discriminant < 0. {
+            return None;
+        }
+        let t = (-half_b - discriminant.sqrt()) / a;
+        if t > t_min && t < t_max {
+            Some((t, self.radiance))
+        } else {
+            None
+        }
+    }
+    fn bounds(&self) -> Option<LightBounds> {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        let area = 4. * PI * self.radius * self.radius;
+        Some(LightBounds::everywhere(
+            Aabb::new(self.center - r, self.center + r),
+            PI * area * luminance(self.radiance),
+        ))
+    }
+}
+
+/// What the LightTree knows of a light, or of a group of them: the
+/// light leaves from inside 'aabb' in directions making at most
+/// theta_o with 'axis', and spreads theta_e past that (Conty Estevez
+/// and Kulla 2018)
+#[derive(Debug, Clone, Copy)]
+pub struct LightBounds {
+    pub aabb: Aabb,
+    /// The power of the light, as a luminance
+    pub power: f32,
+    pub axis: Vec3,
+    pub cos_theta_o: f32,
+    pub cos_theta_e: f32,
+}
+
+impl LightBounds {
+    /// For lights shining the same in every direction
+    pub fn everywhere(aabb: Aabb, power: f32) -> Self {
+        Self {
+            aabb,
+            power,
+            axis: Vec3::new(0., 1., 0.),
+            cos_theta_o: -1.,
+            cos_theta_e: 0.,
+        }
+    }
+    fn centroid(&self) -> Vec3 {
+        (self.aabb.min + self.aabb.max) * 0.5
+    }
+    // Bounds of both, the cones of directions are merged into the
+    // smallest one containing the two
+    fn union(&self, other: &Self) -> Self {
+        let theta_a = self.cos_theta_o.clamp(-1., 1.).acos();
+        let theta_b = other.cos_theta_o.clamp(-1., 1.).acos();
+        let theta_d = self.axis.dot(other.axis).clamp(-1., 1.).acos();
+        let (axis, theta_o) = if (theta_d + theta_b).min(PI) <= theta_a {
+            (self.axis, theta_a)
+        } else if (theta_d + theta_a).min(PI) <= theta_b {
+            (other.axis, theta_b)
+        } else {
+            let theta_o = (theta_a + theta_d + theta_b) / 2.;
+            let around = self.axis.cross(other.axis);
+            if theta_o >= PI || around.squared_len() == 0. {
+                (self.axis, PI)
+            } else {
+                // Turning the first axis towards the second one
+                let turn = Mat4::rotation(around, (theta_o - theta_a).to_degrees());
+                (turn.transform_vector(self.axis).unit_vector(), theta_o)
+            }
+        };
+        Self {
+            aabb: self.aabb.union(&other.aabb),
+            power: self.power + other.power,
+            axis,
+            cos_theta_o: theta_o.cos(),
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+        }
+    }
+    // About how much of the light reaches 'p': the power over the
+    // squared distance, times the cosine of the smallest angle between
+    // the cone of directions and the direction to 'p' from anywhere in
+    // the box. The way PBRT does it, but without the normal at 'p' as
+    // volumes and glass get light from all around
+    fn importance(&self, p: Vec3) -> f32 {
+        let center = self.centroid();
+        let to_p = p - center;
+        // Not too close, so the lights of a box around 'p' do not blow up
+        let diagonal = self.aabb.max - self.aabb.min;
+        let distance2 = to_p.squared_len().max(diagonal.length() / 2.);
+        // cos(a - b) and sin(a - b) from their sines and cosines,
+        // stopping at 0 when b > a
+        let cos_sub = |sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32| {
+            if cos_a > cos_b {
+                1.
+            } else {
+                cos_a * cos_b + sin_a * sin_b
+            }
+        };
+        let sin_sub = |sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32| {
+            if cos_a > cos_b {
+                0.
+            } else {
+                sin_a * cos_b - cos_a * sin_b
+            }
+        };
+        let sin_of = |cos: f32| (1. - cos * cos).max(0.).sqrt();
+        let cos_theta_w = if to_p.squared_len() > 0. {
+            self.axis.dot(to_p.unit_vector())
+        } else {
+            1.
+        };
+        // The cone of directions from the center covering the box
+        let radius2 = diagonal.squared_len() / 4.;
+        let cos_theta_b = if to_p.squared_len() <= radius2 {
+            -1.
+        } else {
+            (1. - radius2 / to_p.squared_len()).max(0.).sqrt()
+        };
+        let (sin_theta_w, sin_theta_o) = (sin_of(cos_theta_w), sin_of(self.cos_theta_o));
+        let cos_theta_x = cos_sub(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta = cos_sub(sin_theta_x, cos_theta_x, sin_of(cos_theta_b), cos_theta_b);
+        if cos_theta <= self.cos_theta_e {
+            return 0.;
+        }
+        self.power * cos_theta / distance2
+    }
+}
+
+// The trails have one bit per level, so the tree is never deeper
+const MAX_LIGHT_DEPTH: usize = 64;
+
+// A node of the tree, its first child comes right after it
+enum LightNode {
+    Leaf(LightBounds, usize),
+    // The bounds of both children and the index of the second one
+    Interior(LightBounds, usize),
+}
+
+impl LightNode {
+    fn bounds(&self) -> &LightBounds {
+        match self {
+            LightNode::Leaf(bounds, _) | LightNode::Interior(bounds, _) => bounds,
+        }
+    }
+}
+
+/// All the lights of the world. The ones with bounds are kept in a
+/// hierarchy (Conty Estevez and Kulla 2018) which is walked down from
+/// the top to pick one of them, going each time towards the group
+/// which looks brighter from the point. With thousands of lights the
+/// few that matter are picked most of the time. Only the Light
+/// implementors are in it: shapes with a DiffuseLight are part of the
+/// geometry and are only found by rays hitting them, use a SphereLight
+/// for a light which should also be looked for with shadow rays
+#[derive(Default)]
+pub struct LightTree {
+    lights: Vec<Box<dyn Light + Sync + Send>>,
+    // The lights without bounds
+    infinite: Vec<usize>,
+    nodes: Vec<LightNode>,
+    // For each light, which child to take at every level on the way
+    // down to it, starting from the lowest bit
+    trails: Vec<u64>,
+}
+
+impl LightTree {
+    /// Adds the lights and builds the hierarchy again
+    pub fn extend(&mut self, lights: impl IntoIterator<Item = Box<dyn Light + Sync + Send>>) {
+        self.lights.extend(lights);
+        self.infinite.clear();
+        self.nodes.clear();
+        self.trails = vec![0; self.lights.len()];
+        let mut bounded = Vec::new();
+        for (i, light) in self.lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) if bounds.power > 0. => bounded.push((i, bounds)),
+                Some(_) => {}
+                None => self.infinite.push(i),
+            }
+        }
+        if !bounded.is_empty() {
+            self.build(&mut bounded, 0, 0);
+        }
+    }
+    /// The lights without bounds, which are sampled every time
+    pub fn infinite(&self) -> impl Iterator<Item = &(dyn Light + Sync + Send)> {
+        self.infinite.iter().map(move |&i| &*self.lights[i])
+    }
+    /// One of the lights with bounds and the probability it had to be
+    /// picked, None when none of them can reach 'p'
+    pub fn sample(&self, p: Vec3) -> Option<(&(dyn Light + Sync + Send), f32)> {
+        let mut rng = rand::thread_rng();
+        let mut pmf = 1.;
+        let mut i = 0;
+        loop {
+            match self.nodes.get(i)? {
+                LightNode::Leaf(bounds, light) => {
+                    // A single light in the tree has not been looked at
+                    if bounds.importance(p) <= 0. {
+                        return None;
+                    }
+                    return Some((&*self.lights[*light], pmf));
+                }
+                LightNode::Interior(_, second) => {
+                    let first = self.nodes[i + 1].bounds().importance(p);
+                    let other = self.nodes[*second].bounds().importance(p);
+                    if first + other <= 0. {
+                        return None;
+                    }
+                    let p_first = first / (first + other);
+                    if rng.gen::<f32>() < p_first {
+                        pmf *= p_first;
+                        i += 1;
+                    } else {
+                        pmf *= 1. - p_first;
+                        i = *second;
+                    }
+                }
+            }
+        }
+    }
+    /// The probability of sample() picking the light 'index' from 'p'
+    pub fn pmf(&self, p: Vec3, index: usize) -> f32 {
+        let mut trail = self.trails[index];
+        let mut pmf = 1.;
+        let mut i = 0;
+        while let Some(LightNode::Interior(_, second)) = self.nodes.get(i) {
+            let first = self.nodes[i + 1].bounds().importance(p);
+            let other = self.nodes[*second].bounds().importance(p);
+            if first + other <= 0. {
+                return 0.;
+            }
+            if trail & 1 == 0 {
+                pmf *= first / (first + other);
+                i += 1;
+            } else {
+                pmf *= other / (first + other);
+                i = *second;
+            }
+            trail >>= 1;
+        }
+        pmf
+    }
+    /// The closest light the ray meets before 't_max' and the light it
+    /// sees there, the boxes of the tree skip the ones it misses
+    pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(usize, Vec3)> {
+        let mut closest = None;
+        let mut t_max = t_max;
+        // Every level leaves at most one node for later, plus the two
+        // children of the deepest one
+        let mut stack = [0; MAX_LIGHT_DEPTH + 1];
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let i = stack[len];
+            let node = match self.nodes.get(i) {
+                Some(node) => node,
+                None => break,
+            };
+            if node.bounds().aabb.hit_range(r, t_min, t_max).is_none() {
+                continue;
+            }
+            match node {
+                LightNode::Leaf(_, light) => {
+                    if let Some((t, radiance)) = self.lights[*light].hit(r, t_min, t_max) {
+                        t_max = t;
+                        closest = Some((*light, radiance));
+                    }
+                }
+                LightNode::Interior(_, second) => {
+                    stack[len] = *second;
+                    stack[len + 1] = i + 1;
+                    len += 2;
+                }
+            }
+        }
+        closest
+    }
+    pub fn get(&self, index: usize) -> &(dyn Light + Sync + Send) {
+        &*self.lights[index]
+    }
+
+    // Splits the lights in two halves along the longest side of the box
+    // of their centers, returns the bounds of the node
+    fn build(
+        &mut self,
+        lights: &mut [(usize, LightBounds)],
+        depth: u32,
+        trail: u64,
+    ) -> LightBounds {
+        let i = self.nodes.len();
+        debug_assert!((depth as usize) < MAX_LIGHT_DEPTH || lights.len() == 1);
+        if lights.len() == 1 {
+            let (light, bounds) = lights[0];
+            self.nodes.push(LightNode::Leaf(bounds, light));
+            self.trails[light] = trail;
+            return bounds;
+        }
+        let centers: Vec<Vec3> = lights.iter().map(|(_, b)| b.centroid()).collect();
+        let extent = Aabb::from_points(&centers);
+        let size = extent.max - extent.min;
+        let axis = |c: Vec3| {
+            if size.x >= size.y && size.x >= size.z {
+                c.x
+            } else if size.y >= size.z {
+                c.y
+            } else {
+                c.z
+            }
+        };
+        lights.sort_by(|a, b| axis(a.1.centroid()).total_cmp(&axis(b.1.centroid())));
+        let (first, second) = lights.split_at_mut(lights.len() / 2);
+        // Filled in once the children are there
+        self.nodes.push(LightNode::Leaf(first[0].1, first[0].0));
+        let a = self.build(first, depth + 1, trail);
+        let second_index = self.nodes.len();
+        let b = self.build(second, depth + 1, trail | 1 << depth);
+        let bounds = a.union(&b);
+        self.nodes[i] = LightNode::Interior(bounds, second_index);
+        bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pmf_matches_sampling() {
+        let mut tree = LightTree::default();
+        let mut lights: Vec<Box<dyn Light + Sync + Send>> = (0..6)
+            .map(|i| {
+                let position = Vec3::new(i as f32 * 1.5 - 4., 2. + (i % 3) as f32, (i % 2) as f32);
+                Box::new(PointLight::new(
+                    position,
+                    Vec3::new(1., 1., 1.) * (i + 1) as f32,
+                )) as Box<dyn Light + Sync + Send>
+            })
+            .collect();
+        lights.push(Box::new(SpotLight::new(
+            Vec3::new(0., 4., 0.),
+            Vec3::new(0., -1., 0.),
+            Vec3::new(5., 5., 5.),
+            30.,
+        )));
+        tree.extend(lights);
+        let p = Vec3::new(0.3, 0., -0.2);
+        let pmf: Vec<f32> = (0..tree.lights.len()).map(|i| tree.pmf(p, i)).collect();
+        assert!((pmf.iter().sum::<f32>() - 1.).abs() < 1e-5);
+
+        let n = 100000;
+        let mut counts = vec![0; tree.lights.len()];
+        for _ in 0..n {
+            let (light, sampled_pmf) = tree.sample(p).unwrap();
+            let i = (0..tree.lights.len())
+                .position(|i| std::ptr::addr_eq(light, &*tree.lights[i]))
+                .unwrap();
+            assert!((sampled_pmf - pmf[i]).abs() < 1e-5);
+            counts[i] += 1;
+        }
+        for (count, pmf) in counts.iter().zip(pmf) {
+            assert!((*count as f32 / n as f32 - pmf).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn hit_finds_the_closest_light() {
+        let mut tree = LightTree::default();
+        // A row of balls along x, so the ray goes through many boxes
+        tree.extend((0..40).map(|i| {
+            let center = Vec3::new(i as f32, (i % 5) as f32 * 0.1, (i % 3) as f32 * 0.2);
+            Box::new(SphereLight::new(
+                center,
+                0.3,
+                Vec3::new(1., 1., 1.) * (i + 1) as f32,
+            )) as Box<dyn Light + Sync + Send>
+        }));
+        for origin in [Vec3::new(-5., 0.1, 0.1), Vec3::new(45., 0.2, 0.2)] {
+            let r = Ray::new(origin, Vec3::new(20., 0.2, 0.1) - origin);
+            let expected = (0..tree.lights.len())
+                .filter_map(|i| Some((tree.lights[i].hit(&r, 0.001, f32::MAX)?.0, i)))
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, i)| i);
+            assert!(expected.is_some());
+            assert_eq!(tree.hit(&r, 0.001, f32::MAX).map(|(i, _)| i), expected);
+        }
+    }
+}
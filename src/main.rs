@@ -1,14 +1,29 @@
+mod csg;
+mod distribution;
 mod hitables;
+mod lights;
 mod materials;
+mod microfacet;
 mod mylib;
+mod noise;
+mod planar;
+mod principled;
+mod quadrics;
+mod sdf;
+mod sky;
+mod spectrum;
+mod textures;
 mod vecmath;
+mod volumes;
 
 use hitables::*;
+use lights::LightTree;
 use materials::*;
 use mylib::*;
 
 // use mylib::{HitableList,Camera};
 use vecmath::Vec3;
+use volumes::ConstantMedium;
 
 use minifb::{Key, Window, WindowOptions};
 use rand::Rng;
@@ -20,8 +35,22 @@ const WIDTH: usize = 640;
 const HEIGHT: usize = 320;
 const SECS: usize = 65; //100
 
-const USE_RANDOM_SCENE: bool = true;
+// One of "simple", "random", "instances", "cornell", "quadrics",
+// "csg", "cornell_smoke", "cloud", "sdf", "metals",
+// "glass", "principled", "prism", "coated",
+// "rough_diffuse", "iridescent", "subsurface",
+// "mix", "cutout", "bumpy", "noise", "image", "lights",
+// "daylight", "environment", "many_lights"
+const SCENE: &str = "random";
 const USE_MULTITHREADING: bool = true;
+// Traces wavelengths instead of RGB, slower and noisier
+// in color but shows dispersion, try it on "prism"
+const SPECTRAL: bool = false;
+
+// Fills everything closer than FOG_RADIUS to the origin
+// with fog, a density of 0 turns it off
+const FOG_DENSITY: f32 = 0.;
+const FOG_RADIUS: f32 = 100.;
 
 fn main() {
     let mut stdout = std::io::stdout();
@@ -37,23 +66,35 @@ fn main() {
         panic!("{}", e);
     });
 
-    let lookfrom = Vec3::new(13., 2., 3.);
-    let lookat = Vec3::new(0., 0., 0.);
-    let dist_to_focus = 10.;//(lookfrom - lookat).length();
-    let aperture = 0.1;
-    let cam = Camera::new(
-        lookfrom,
-        lookat,
-        Vec3::new(0., 1., 0.),
-        20.,
-        WIDTH as f32 / HEIGHT as f32,
-        aperture,
-        dist_to_focus,
-    );
+    let cam = if SCENE.starts_with("cornell") {
+        Camera::new(
+            Vec3::new(278., 278., -800.),
+            Vec3::new(278., 278., 0.),
+            Vec3::new(0., 1., 0.),
+            40.,
+            WIDTH as f32 / HEIGHT as f32,
+            0.,
+            10.,
+        )
+    } else {
+        let lookfrom = Vec3::new(13., 2., 3.);
+        let lookat = Vec3::new(0., 0., 0.);
+        let dist_to_focus = 10.;//(lookfrom - lookat).length();
+        let aperture = 0.1;
+        Camera::new(
+            lookfrom,
+            lookat,
+            Vec3::new(0., 1., 0.),
+            20.,
+            WIDTH as f32 / HEIGHT as f32,
+            aperture,
+            dist_to_focus,
+        )
+    };
+    let cam = cam.with_resolution(WIDTH, HEIGHT);
 
-    let world;
-    if !USE_RANDOM_SCENE {
-        world = HitableList {
+    let mut world = match SCENE {
+        "simple" => HitableList {
             list: vec![
                 Box::new(Sphere::new(
                     Vec3::new(0., 0., -1.),
@@ -77,9 +118,41 @@ fn main() {
                 )),
                 // Box::new(Sphere::new(Vec3::new(-1.,0.,-1.),-0.45,Materials::Dieletric(1.5))),
             ],
-        };
-    } else {
-        world = HitableList::random_scene();
+            lights: LightTree::default(),
+            sky: None,
+        },
+        "instances" => HitableList::instances_scene(),
+        "cornell" => HitableList::cornell_box(),
+        "quadrics" => HitableList::quadrics_scene(),
+        "csg" => HitableList::csg_scene(),
+        "cornell_smoke" => HitableList::cornell_smoke(),
+        "cloud" => HitableList::cloud_scene(),
+        "sdf" => HitableList::sdf_scene(),
+        "metals" => HitableList::metals_scene(),
+        "glass" => HitableList::glass_scene(),
+        "principled" => HitableList::principled_scene(),
+        "prism" => HitableList::prism_scene(),
+        "coated" => HitableList::coated_scene(),
+        "rough_diffuse" => HitableList::rough_diffuse_scene(),
+        "iridescent" => HitableList::iridescent_scene(),
+        "subsurface" => HitableList::subsurface_scene(),
+        "mix" => HitableList::mix_scene(),
+        "cutout" => HitableList::cutout_scene(),
+        "bumpy" => HitableList::bumpy_scene(),
+        "noise" => HitableList::noise_scene(),
+        "image" => HitableList::image_scene(),
+        "lights" => HitableList::lights_scene(),
+        "daylight" => HitableList::daylight_scene(),
+        "environment" => HitableList::environment_scene(),
+        "many_lights" => HitableList::many_lights_scene(),
+        _ => HitableList::random_scene(),
+    };
+    if FOG_DENSITY > 0. {
+        world.list.push(Box::new(ConstantMedium::atmosphere(
+            FOG_RADIUS,
+            FOG_DENSITY,
+            Vec3::new(1., 1., 1.),
+        )));
     }
 
     if USE_MULTITHREADING {
@@ -129,9 +202,21 @@ fn calc_col(i: usize, j: usize, world: &HitableList, cam: &Camera) -> Vec3 {
         let u = (i as f32 + rng.gen::<f32>()) / WIDTH as f32;
         let v = ((HEIGHT - 1 - j) as f32 + rng.gen::<f32>()) / HEIGHT as f32;
         let r = cam.get_ray(u, v);
-        col += Vec3::color_material(&r, &world, 0);
+        if SPECTRAL {
+            let wavelengths = spectrum::sample_wavelengths(rng.gen());
+            let radiance = Vec3::color_material(&r, world, Some(wavelengths), 0);
+            col += spectrum::spectrum_to_xyz(radiance, wavelengths);
+        } else {
+            col += Vec3::color_material(&r, world, None, 0);
+        }
     }
     col /= SECS as f32;
+    if SPECTRAL {
+        // Single samples can be outside of sRGB, only the average
+        // can be clipped
+        col = spectrum::xyz_to_rgb(col);
+        col = Vec3::new(col.x.max(0.), col.y.max(0.), col.z.max(0.));
+    }
 
     // The following inreases the gamma, the guide mentions that
     // Image viewers lower the gamma making the picture appear
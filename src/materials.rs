@@ -1,7 +1,11 @@
 use crate::hitables::*;
+use crate::microfacet::*;
 use crate::mylib::*;
+use crate::spectrum::*;
+use crate::textures::*;
 use crate::vecmath::Vec3;
 use rand::Rng;
+use std::sync::Arc;
 
 // The direction after a ray has been reflected off
 // a metal surface
@@ -34,6 +38,16 @@ fn schlick(cosine: f32, ref_idx: f32) -> f32 {
     return r0 + (1. - r0) * f32::powf(1. - cosine, 5.);
 }
 
+/// The kind of lobe a material scattered a ray with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lobe {
+    /// Described by eval() and pdf(), so lights can be sampled for it
+    Smooth,
+    /// A mirror or a refraction, a single direction which shadow rays
+    /// will never find
+    Delta,
+}
+
 pub trait Material {
     fn scatter(
         &self,
@@ -42,14 +56,93 @@ pub trait Material {
         attenuation: &mut Vec3,
         scattered: &mut Ray,
     ) -> bool;
+    /// Light given off by the surface, black for everything but lights
+    fn emitted(&self, _rec: &HitRecord) -> Vec3 {
+        Vec3::new(0., 0., 0.)
+    }
+    /// Same as scatter() when rendering in spectral mode, where the
+    /// attenuation is given for each of the three wavelengths. Unless
+    /// the material depends on the wavelength, the color given by
+    /// scatter() is simply turned into a spectrum
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: Vec3,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        if !self.scatter(r_in, rec, attenuation, scattered) {
+            return false;
+        }
+        *attenuation = rgb_to_spectrum(*attenuation, wavelengths);
+        true
+    }
+    /// What the integrator calls: scatter(), or scatter_spectral() when
+    /// given the wavelengths, also telling which kind of lobe picked
+    /// the direction. By default it is a smooth one when eval() is not
+    /// black there, materials made of several lobes know better
+    fn scatter_lobe(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: Option<Vec3>,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> Option<Lobe> {
+        let scatters = match wavelengths {
+            Some(lambda) => self.scatter_spectral(r_in, rec, lambda, attenuation, scattered),
+            None => self.scatter(r_in, rec, attenuation, scattered),
+        };
+        if !scatters {
+            return None;
+        }
+        let f = self.eval(r_in, rec, scattered.get_direction().unit_vector());
+        if f.x > 0. || f.y > 0. || f.z > 0. {
+            Some(Lobe::Smooth)
+        } else {
+            Some(Lobe::Delta)
+        }
+    }
+    /// How much of the surface is there at the hit, from 0 where rays
+    /// go through as if it was not there to 1 (the default) where they
+    /// stop. Looked at by the intersection, see HitRecord::is_opaque()
+    fn opacity(&self, _rec: &HitRecord) -> f32 {
+        1.
+    }
+    /// How much of the light arriving from the unit direction 'wi' is
+    /// sent back along the ray: the BSDF times the cosine with the
+    /// normal. Used for lights found with shadow rays. Mirrors, clear
+    /// glass and the like only scatter in a few directions which a
+    /// light will never be exactly in, so by default it is black.
+    /// Subsurface is black too: light only gets in through its smooth
+    /// boundary, so it sees the sky and the sphere lights its rays find
+    /// but not the point, spot and directional lights
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> Vec3 {
+        Vec3::new(0., 0., 0.)
+    }
+    /// Same as eval() in spectral mode, like scatter_spectral()
+    fn eval_spectral(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3, wavelengths: Vec3) -> Vec3 {
+        rgb_to_spectrum(self.eval(r_in, rec, wi), wavelengths)
+    }
+    /// The density of scatter() picking the unit direction 'wi', per
+    /// unit of solid angle. Used to share the light found both by the
+    /// shadow rays and by scattering between the two. When it is not
+    /// known (the default 0) the shadow rays get all of it
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> f32 {
+        0.
+    }
 }
 
 pub struct Lambertian {
-    albedo: Vec3,
+    albedo: Arc<dyn Texture + Sync + Send>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Vec3) -> Self {
+        Self::textured(Arc::new(SolidColor::new(albedo)))
+    }
+    pub fn textured(albedo: Arc<dyn Texture + Sync + Send>) -> Self {
         Self { albedo }
     }
 }
@@ -64,11 +157,112 @@ impl Material for Lambertian {
     ) -> bool {
         let rec_p = rec.get_p();
         let rec_normal = rec.get_normal();
-        let target = rec_p + rec_normal + Vec3::random_in_unit_sphere();
+        // A point on the unit sphere, not inside it, gives exactly
+        // the cosine distribution that pdf() tells
+        let target = rec_p + rec_normal + Vec3::random_unit_vector();
         *scattered = Ray::new(rec_p, target - rec_p);
-        *attenuation = self.albedo;
+        *attenuation = rec.lookup(&*self.albedo);
         return true;
     }
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Vec3 {
+        let cos = rec.get_normal().dot(wi).max(0.);
+        rec.lookup(&*self.albedo) * (cos / std::f32::consts::PI)
+    }
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        rec.get_normal().dot(wi).max(0.) / std::f32::consts::PI
+    }
+}
+
+/// A rough diffuse surface (Oren and Nayar 1994), made of tiny
+/// Lambertian facets whose slopes have a standard deviation of 'sigma'
+/// degrees. Light is sent back towards where it came from more than
+/// with Lambertian, so rough things like clay or the moon look flat
+/// instead of darkening towards their edges. With a sigma of 0 it is
+/// the same as Lambertian
+pub struct OrenNayar {
+    albedo: Arc<dyn Texture + Sync + Send>,
+    sigma: Arc<dyn Texture + Sync + Send>,
+}
+
+impl OrenNayar {
+    pub fn new(albedo: Vec3, sigma: f32) -> Self {
+        Self::textured(
+            Arc::new(SolidColor::new(albedo)),
+            Arc::new(SolidColor::scalar(sigma)),
+        )
+    }
+    /// The first channel of 'sigma' is the roughness in degrees
+    pub fn textured(
+        albedo: Arc<dyn Texture + Sync + Send>,
+        sigma: Arc<dyn Texture + Sync + Send>,
+    ) -> Self {
+        Self { albedo, sigma }
+    }
+    /// The BRDF times pi, in the local space of the normal, through the
+    /// usual approximation of the model
+    fn reflectance(albedo: Vec3, sigma_degrees: f32, wo: Vec3, wi: Vec3) -> Vec3 {
+        let sigma2 = sigma_degrees.to_radians().powi(2);
+        let a = 1. - sigma2 / (2. * (sigma2 + 0.33));
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+        let sin_i = (1. - wi.z * wi.z).max(0.).sqrt();
+        let sin_o = (1. - wo.z * wo.z).max(0.).sqrt();
+        // Cosine of the angle between the two directions around the normal
+        let cos_phi = if sin_i > 1e-4 && sin_o > 1e-4 {
+            ((wi.x * wo.x + wi.y * wo.y) / (sin_i * sin_o)).max(0.)
+        } else {
+            0.
+        };
+        // sin(alpha) * tan(beta), with alpha the larger of the two
+        // angles with the normal and beta the smaller
+        let sin_alpha_tan_beta = if wi.z.abs() > wo.z.abs() {
+            sin_o * sin_i / wi.z.abs()
+        } else {
+            sin_i * sin_o / wo.z.abs()
+        };
+        albedo * (a + b * cos_phi * sin_alpha_tan_beta)
+    }
+}
+
+impl Material for OrenNayar {
+    /// Directions are picked like for a Lambertian surface, so the
+    /// attenuation is the ratio between the two BRDFs
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let frame = Frame::new(rec.get_normal());
+        let wo = frame.to_local(r_in.get_direction().unit_vector() * -1.);
+        if wo.z <= 0. {
+            return false;
+        }
+        let wi = Vec3::random_cosine_direction();
+        let albedo = rec.lookup(&*self.albedo);
+        let sigma = rec.lookup(&*self.sigma).x;
+        *attenuation = Self::reflectance(albedo, sigma, wo, wi);
+        *scattered = Ray::new(rec.get_p(), frame.to_world(wi));
+        true
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Vec3 {
+        let frame = Frame::new(rec.get_normal());
+        let wo = frame.to_local(r_in.get_direction().unit_vector() * -1.);
+        let wi = frame.to_local(wi);
+        if wo.z <= 0. || wi.z <= 0. {
+            return Vec3::new(0., 0., 0.);
+        }
+        let albedo = rec.lookup(&*self.albedo);
+        let sigma = rec.lookup(&*self.sigma).x;
+        Self::reflectance(albedo, sigma, wo, wi) * (wi.z / std::f32::consts::PI)
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        let n = rec.get_normal();
+        if r_in.get_direction().dot(n) >= 0. {
+            return 0.;
+        }
+        n.dot(wi).max(0.) / std::f32::consts::PI
+    }
 }
 
 pub struct Metal {
@@ -104,61 +298,1240 @@ impl Material for Metal {
         *attenuation = self.albedo;
         return scattered.get_direction().dot(rec.get_normal()) > 0.;
     }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Vec3 {
+        self.albedo * self.pdf(r_in, rec, wi)
+    }
+    /// The scattered direction points somewhere in a ball of radius
+    /// 'fuzziness' around the tip of the reflection, so its density is
+    /// the volume of the ball along 'wi', r^2 dr over the chord
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        let fuzziness = self.fuzziness.min(1.);
+        if fuzziness <= 0. || wi.dot(rec.get_normal()) <= 0. {
+            return 0.;
+        }
+        let reflected = reflect(&r_in.get_direction().unit_vector(), &rec.get_normal());
+        let c = wi.dot(reflected);
+        let discriminant = c * c - 1. + fuzziness * fuzziness;
+        if discriminant <= 0. {
+            return 0.;
+        }
+        let t1 = (c - discriminant.sqrt()).max(0.);
+        let t2 = (c + discriminant.sqrt()).max(0.);
+        (t2.powi(3) - t1.powi(3)) / (4. * std::f32::consts::PI * fuzziness.powi(3))
+    }
+}
+
+/// How much of the light is left after going from the last hit to this
+/// one, when this one leaves the object: we travelled inside it, and it
+/// absorbed a fraction of the light for each unit of length following
+/// the Beer-Lambert law
+fn absorption_transmittance(r_in: &Ray, rec: &HitRecord, absorption: Vec3) -> Vec3 {
+    if rec.get_front_face() {
+        return Vec3::new(1., 1., 1.);
+    }
+    let distance = rec.get_t() * r_in.get_direction().length();
+    Vec3::new(
+        (-absorption.x * distance).exp(),
+        (-absorption.y * distance).exp(),
+        (-absorption.z * distance).exp(),
+    )
+}
+
+/// Absorption coefficient which leaves 'color' of the light after
+/// 'distance' units
+fn absorption_from_transmittance(color: Vec3, distance: f32) -> Vec3 {
+    let sigma = |c: f32| -c.max(1e-6).ln() / distance;
+    Vec3::new(sigma(color.x), sigma(color.y), sigma(color.z))
+}
+
+/// A transparent film a few hundred nanometers thick over a surface,
+/// like soap, oil on water or the oxide on heated metal. The light
+/// reflected on its two sides interferes and gives colors changing
+/// with the angle and the thickness, which comes from the first
+/// channel of a texture
+struct ThinFilm {
+    ior: f32,
+    thickness: Arc<dyn Texture + Sync + Send>,
+}
+
+impl ThinFilm {
+    /// Fraction of light reflected when coming from a medium of index
+    /// 'outside' onto a material of index eta + i * k, given by 'inside'
+    /// for each wavelength. Read at the wavelengths of the path in
+    /// spectral mode, otherwise the color of the whole spectrum
+    fn reflectance(
+        &self,
+        rec: &HitRecord,
+        cos_theta: f32,
+        outside: f32,
+        inside: impl Fn(f32) -> (f32, f32),
+        wavelengths: Option<Vec3>,
+    ) -> Vec3 {
+        let thickness = rec.lookup(&*self.thickness).x;
+        let at = |lambda: f32| {
+            let (eta, k) = inside(lambda);
+            fresnel_thin_film(cos_theta, outside, self.ior, thickness, eta, k, lambda)
+        };
+        match wavelengths {
+            Some(l) => Vec3::new(at(l.x), at(l.y), at(l.z)),
+            None => reflectance_to_rgb(at),
+        }
+    }
+}
+
+/// How the index of refraction of a transparent material changes with
+/// the wavelength, in nanometers. Only matters in spectral mode
+#[derive(Debug, Clone, Copy)]
+pub enum Ior {
+    Constant(f32),
+    /// a + b / lambda^2, with lambda in micrometers
+    Cauchy { a: f32, b: f32 },
+    /// The Sellmeier equation, with the 'c' coefficients in square
+    /// micrometers like in glass catalogs
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Ior {
+    pub fn at(&self, lambda: f32) -> f32 {
+        let l2 = (lambda / 1000.).powi(2);
+        match *self {
+            Ior::Constant(n) => n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                let sum: f32 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1. + sum).sqrt()
+            }
+        }
+    }
+    fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
 }
 
 pub struct Dieletric {
+    ior: Ior,
+    // The index when rendering in RGB
     ref_idx: f32,
+    absorption: Vec3,
+    film: Option<ThinFilm>,
 }
 
 impl Dieletric {
     pub fn new(ref_idx: f32) -> Self {
-        Self { ref_idx }
+        Self::with_ior(Ior::Constant(ref_idx))
+    }
+    /// A glass which splits light in its colors in spectral mode
+    pub fn with_ior(ior: Ior) -> Self {
+        Self {
+            ior,
+            ref_idx: ior.at(SODIUM_D_LINE),
+            absorption: Vec3::new(0., 0., 0.),
+            film: None,
+        }
+    }
+    pub fn cauchy(a: f32, b: f32) -> Self {
+        Self::with_ior(Ior::Cauchy { a, b })
+    }
+    pub fn sellmeier(b: [f32; 3], c: [f32; 3]) -> Self {
+        Self::with_ior(Ior::Sellmeier { b, c })
+    }
+    /// The most common optical glass
+    pub fn bk7() -> Self {
+        Self::sellmeier(
+            [1.0396122, 0.23179235, 1.0104694],
+            [0.0060006985, 0.020017914, 103.56065],
+        )
+    }
+    /// A dense flint glass, which disperses a lot
+    pub fn sf11() -> Self {
+        Self::sellmeier(
+            [1.737597, 0.31374735, 1.8987811],
+            [0.013188707, 0.062306814, 155.2363],
+        )
+    }
+    /// Coloured glass, absorbing this much of each channel per unit of
+    /// length travelled inside, so thick glass is darker than thin glass.
+    /// The object must be closed for the length to make sense
+    pub fn with_absorption(mut self, absorption: Vec3) -> Self {
+        self.absorption = absorption;
+        self
+    }
+    /// Same as with_absorption(), but saying which color the light
+    /// takes after 'distance' units inside, which is easier to pick
+    pub fn with_transmittance(self, color: Vec3, distance: f32) -> Self {
+        self.with_absorption(absorption_from_transmittance(color, distance))
+    }
+    /// Covers the surface with a thin film of index 'ior', 'thickness'
+    /// gives how many nanometers thick it is. With an index of 1 for
+    /// the glass itself this is a soap bubble
+    pub fn with_thin_film(mut self, ior: f32, thickness: Arc<dyn Texture + Sync + Send>) -> Self {
+        self.film = Some(ThinFilm { ior, thickness });
+        self
     }
-}
 
-impl Material for Dieletric {
-    fn scatter(
+    // What scatter() does, with the index of refraction given, and in
+    // spectral mode the wavelengths
+    fn refract_or_reflect(
         &self,
         r_in: &Ray,
         rec: &HitRecord,
+        ref_idx: f32,
+        wavelengths: Option<Vec3>,
         attenuation: &mut Vec3,
         scattered: &mut Ray,
     ) -> bool {
         let outward_normal: Vec3;
         let reflected: Vec3 = reflect(&r_in.get_direction(), &rec.get_normal());
         let ni_over_nt: f32;
-        *attenuation = Vec3::new(1., 1., 1.);
+        let absorption = absorption_transmittance(r_in, rec, self.absorption);
+        *attenuation = match wavelengths {
+            Some(lambda) => rgb_to_spectrum(absorption, lambda),
+            None => absorption,
+        };
         let mut refracted: Vec3 = Vec3::new(0., 0., 0.);
-        let reflect_prob: f32;
         let cosine: f32;
-        if r_in.get_direction().dot(rec.get_normal()) > 0. {
-            outward_normal = rec.get_normal() * (-1.);
-            ni_over_nt = self.ref_idx;
-            cosine = r_in.get_direction().dot(rec.get_normal()) * self.ref_idx
-                / r_in.get_direction().length();
+        // The normal always faces the incoming ray, the front face
+        // tells us whether we are entering or leaving the object
+        outward_normal = rec.get_normal();
+        if !rec.get_front_face() {
+            ni_over_nt = ref_idx;
+            cosine = r_in.get_direction().dot(rec.get_normal()) * ref_idx
+                / -r_in.get_direction().length();
         } else {
-            outward_normal = rec.get_normal();
-            ni_over_nt = 1. / self.ref_idx;
-            cosine =
-                r_in.get_direction().dot(rec.get_normal()) / (-1. * r_in.get_direction().length());
+            ni_over_nt = 1. / ref_idx;
+            cosine = r_in.get_direction().dot(rec.get_normal()) / -r_in.get_direction().length();
         }
 
-        if refract(
+        // How much is reflected, for each channel
+        let reflectance = if refract(
             &r_in.get_direction(),
             &outward_normal,
             ni_over_nt,
             &mut refracted,
         ) {
-            reflect_prob = schlick(cosine, self.ref_idx);
+            match &self.film {
+                Some(film) => {
+                    let cos_incident = r_in.get_direction().dot(rec.get_normal())
+                        / -r_in.get_direction().length();
+                    let (outside, inside) = if rec.get_front_face() {
+                        (1., ref_idx)
+                    } else {
+                        (ref_idx, 1.)
+                    };
+                    film.reflectance(rec, cos_incident, outside, |_| (inside, 0.), wavelengths)
+                }
+                None => {
+                    let r = schlick(cosine, ref_idx);
+                    Vec3::new(r, r, r)
+                }
+            }
         } else {
-            *scattered = Ray::new(rec.get_p(), reflected);
-            reflect_prob = 1.;
-        }
+            Vec3::new(1., 1., 1.)
+        };
+        // A colored reflectance is followed on average, and the
+        // attenuation makes up for the channels it favors
+        let reflect_prob = (reflectance.x + reflectance.y + reflectance.z) / 3.;
         if rand::thread_rng().gen::<f32>() < reflect_prob {
             *scattered = Ray::new(rec.get_p(), reflected);
+            *attenuation = *attenuation * reflectance / reflect_prob;
         } else {
             *scattered = Ray::new(rec.get_p(), refracted);
+            *attenuation =
+                *attenuation * (Vec3::new(1., 1., 1.) - reflectance) / (1. - reflect_prob);
         }
         return true;
     }
 }
+
+impl Material for Dieletric {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.refract_or_reflect(r_in, rec, self.ref_idx, None, attenuation, scattered)
+    }
+    /// A dispersive glass bends each wavelength its own way, the path
+    /// follows the hero one
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: Vec3,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let ref_idx = self.ior.at(wavelengths.x);
+        if !self.refract_or_reflect(r_in, rec, ref_idx, Some(wavelengths), attenuation, scattered) {
+            return false;
+        }
+        if self.ior.is_dispersive() {
+            *attenuation = terminate_secondary(*attenuation);
+        }
+        true
+    }
+}
+
+/// A surface that emits light, it does not reflect anything
+pub struct DiffuseLight {
+    emit: Vec3,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Vec3) -> Self {
+        Self { emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _attenuation: &mut Vec3,
+        _scattered: &mut Ray,
+    ) -> bool {
+        false
+    }
+    /// Only the front face emits, so a quad light shines on one side
+    fn emitted(&self, rec: &HitRecord) -> Vec3 {
+        if rec.get_front_face() {
+            self.emit
+        } else {
+            Vec3::new(0., 0., 0.)
+        }
+    }
+}
+
+/// The phase function of a volume that scatters light the same
+/// way in every direction
+pub struct Isotropic {
+    albedo: Vec3,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Vec3) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        *scattered = Ray::new(rec.get_p(), Vec3::random_unit_vector());
+        *attenuation = self.albedo;
+        true
+    }
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> Vec3 {
+        self.albedo / (4. * std::f32::consts::PI)
+    }
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _wi: Vec3) -> f32 {
+        1. / (4. * std::f32::consts::PI)
+    }
+}
+
+/// The Henyey-Greenstein phase function, for volumes that scatter
+/// more forwards (g > 0), like clouds, or more backwards (g < 0).
+/// With g = 0 it is the same as Isotropic
+pub struct HenyeyGreenstein {
+    albedo: Vec3,
+    g: f32,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(albedo: Vec3, g: f32) -> Self {
+        Self {
+            albedo,
+            g: g.clamp(-0.99, 0.99),
+        }
+    }
+}
+
+// A new direction for light going along 'forward', the cosine of the
+// angle between the two is sampled exactly from the phase function
+fn sample_henyey_greenstein(forward: Vec3, g: f32) -> Vec3 {
+    let mut rng = rand::thread_rng();
+    let xi = rng.gen::<f32>();
+    let cos_theta = if g.abs() < 1e-3 {
+        1. - 2. * xi
+    } else {
+        let s = (1. - g * g) / (1. - g + 2. * g * xi);
+        ((1. + g * g - s * s) / (2. * g)).clamp(-1., 1.)
+    };
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * std::f32::consts::PI * rng.gen::<f32>();
+    let (t, b) = forward.orthonormal_basis();
+    forward * cos_theta + t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin())
+}
+
+impl Material for HenyeyGreenstein {
+    /// The direction is sampled exactly from the phase function, so the
+    /// weight is the albedo
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let forward = r_in.get_direction().unit_vector();
+        *scattered = Ray::new(rec.get_p(), sample_henyey_greenstein(forward, self.g));
+        *attenuation = self.albedo;
+        true
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Vec3 {
+        self.albedo * self.pdf(r_in, rec, wi)
+    }
+    /// The phase function itself
+    fn pdf(&self, r_in: &Ray, _rec: &HitRecord, wi: Vec3) -> f32 {
+        let cos = r_in.get_direction().unit_vector().dot(wi);
+        let g = self.g;
+        (1. - g * g) / (4. * std::f32::consts::PI * (1. + g * g - 2. * g * cos).powf(1.5))
+    }
+}
+
+/// Translucent materials like wax, marble or skin: light goes through
+/// the surface like with Dieletric, then bounces around inside until it
+/// gets out again or is absorbed. The walk happens between the hits on
+/// the inside of the surface: every time the ray reaches it from
+/// inside we check whether it should have scattered before. The object
+/// must be closed, and as every step inside counts as a bounce the
+/// mean free path should not be too small compared to it. Lights
+/// without a size do not light it, see Material::eval()
+pub struct Subsurface {
+    boundary: Dieletric,
+    sigma_s: Vec3,
+    sigma_a: Vec3,
+    g: f32,
+}
+
+impl Subsurface {
+    /// How much light is scattered and absorbed for each unit of
+    /// length, for each channel
+    pub fn new(ref_idx: f32, sigma_s: Vec3, sigma_a: Vec3) -> Self {
+        Self {
+            boundary: Dieletric::new(ref_idx),
+            sigma_s,
+            sigma_a,
+            g: 0.,
+        }
+    }
+    /// Easier to pick: 'albedo' is the fraction of light scattered
+    /// rather than absorbed at each bounce, and light travels on
+    /// average 'mean_free_path' units between two of them
+    pub fn from_albedo(ref_idx: f32, albedo: Vec3, mean_free_path: Vec3) -> Self {
+        let sigma_t = Vec3::new(
+            1. / mean_free_path.x,
+            1. / mean_free_path.y,
+            1. / mean_free_path.z,
+        );
+        let sigma_s = albedo * sigma_t;
+        Self::new(ref_idx, sigma_s, sigma_t - sigma_s)
+    }
+    /// Scatters with the Henyey-Greenstein phase function instead of
+    /// evenly in every direction
+    pub fn with_anisotropy(mut self, g: f32) -> Self {
+        self.g = g.clamp(-0.99, 0.99);
+        self
+    }
+}
+
+impl Material for Subsurface {
+    /// The distance to the next bounce is sampled for one of the
+    /// channels, picked at random, and the weight accounts for the
+    /// density of all three
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        if rec.get_front_face() {
+            return self.boundary.scatter(r_in, rec, attenuation, scattered);
+        }
+        let mut rng = rand::thread_rng();
+        let direction = r_in.get_direction();
+        let length = direction.length();
+        let distance = rec.get_t() * length;
+        let sigma_t = self.sigma_s + self.sigma_a;
+        let transmittance = |d: f32| {
+            Vec3::new(
+                (-sigma_t.x * d).exp(),
+                (-sigma_t.y * d).exp(),
+                (-sigma_t.z * d).exp(),
+            )
+        };
+        let mean = |v: Vec3| (v.x + v.y + v.z) / 3.;
+        let sigma = match rng.gen_range(0..3) {
+            0 => sigma_t.x,
+            1 => sigma_t.y,
+            _ => sigma_t.z,
+        };
+        let s = -(1. - rng.gen::<f32>()).ln() / sigma;
+        if s < distance {
+            let tr = transmittance(s);
+            *attenuation = self.sigma_s * tr / mean(sigma_t * tr);
+            let p = r_in.get_origin() + direction * (s / length);
+            *scattered = Ray::new(p, sample_henyey_greenstein(direction / length, self.g));
+            return true;
+        }
+        // Made it to the surface
+        let tr = transmittance(distance);
+        if !self.boundary.scatter(r_in, rec, attenuation, scattered) {
+            return false;
+        }
+        *attenuation = *attenuation * tr / mean(tr);
+        true
+    }
+}
+
+/// A physically based metal: a rough surface made of tiny mirrors
+/// whose normals follow the GGX distribution. The color comes from
+/// the complex index of refraction (eta + i*k) of the metal, through
+/// the Fresnel equations, so it changes towards white at grazing angles
+pub struct Conductor {
+    eta: Vec3,
+    k: Vec3,
+    distribution: Ggx,
+    film: Option<ThinFilm>,
+}
+
+impl Conductor {
+    pub fn new(eta: Vec3, k: Vec3, roughness: f32) -> Self {
+        Self::anisotropic(eta, k, roughness, roughness)
+    }
+    /// Brushed metal, 'roughness_x' is along the first tangent of the
+    /// surface (the direction u grows in) and 'roughness_y' across it
+    pub fn anisotropic(eta: Vec3, k: Vec3, roughness_x: f32, roughness_y: f32) -> Self {
+        Self {
+            eta,
+            k,
+            distribution: Ggx::new(roughness_x, roughness_y),
+            film: None,
+        }
+    }
+    /// Covers the metal with a thin film, like the layer of oxide
+    /// which gives colors to heated steel or titanium
+    pub fn with_thin_film(mut self, ior: f32, thickness: Arc<dyn Texture + Sync + Send>) -> Self {
+        self.film = Some(ThinFilm { ior, thickness });
+        self
+    }
+    // What scatter() does, in spectral mode when given the wavelengths
+    fn reflect_on_microfacet(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: Option<Vec3>,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let mut rng = rand::thread_rng();
+        let frame = Frame::from_tangent(rec.get_normal(), rec.get_tangents().0);
+        let wo = frame.to_local(-r_in.get_direction().unit_vector());
+        if wo.z <= 0. {
+            return false;
+        }
+        let m = self.distribution.sample_visible_normal(wo, rng.gen(), rng.gen());
+        let wi = reflect(&-wo, &m);
+        // Reflected into the surface, it would hit another microfacet
+        if wi.z <= 0. {
+            return false;
+        }
+        let fresnel = self.fresnel(rec, wo.dot(m), wavelengths);
+        *attenuation = fresnel * (self.distribution.g2(wo, wi) / self.distribution.g1(wo));
+        *scattered = Ray::new(rec.get_p(), frame.to_world(wi));
+        true
+    }
+    // What eval() gives, in spectral mode when given the wavelengths
+    fn reflectance(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wi: Vec3,
+        wavelengths: Option<Vec3>,
+    ) -> Vec3 {
+        let frame = Frame::from_tangent(rec.get_normal(), rec.get_tangents().0);
+        let wo = frame.to_local(-r_in.get_direction().unit_vector());
+        let wi = frame.to_local(wi);
+        if wo.z <= 0. || wi.z <= 0. {
+            return Vec3::new(0., 0., 0.);
+        }
+        let h = (wo + wi).unit_vector();
+        let d = self.distribution.d(h) * self.distribution.g2(wo, wi) / (4. * wo.z);
+        self.fresnel(rec, wo.dot(h), wavelengths) * d
+    }
+    fn fresnel(&self, rec: &HitRecord, cos: f32, wavelengths: Option<Vec3>) -> Vec3 {
+        match &self.film {
+            Some(film) => {
+                let inside = |lambda| (spectrum_at(self.eta, lambda), spectrum_at(self.k, lambda));
+                film.reflectance(rec, cos, 1., inside, wavelengths)
+            }
+            None => {
+                let f = fresnel_conductor(cos, self.eta, self.k);
+                match wavelengths {
+                    Some(lambda) => rgb_to_spectrum(f, lambda),
+                    None => f,
+                }
+            }
+        }
+    }
+    // Measured indices of refraction, for red, green and blue
+    pub fn gold(roughness: f32) -> Self {
+        Self::new(Vec3::new(0.143, 0.374, 1.442), Vec3::new(3.983, 2.386, 1.603), roughness)
+    }
+    pub fn copper(roughness: f32) -> Self {
+        Self::new(Vec3::new(0.200, 0.924, 1.102), Vec3::new(3.912, 2.452, 2.142), roughness)
+    }
+    pub fn aluminium(roughness: f32) -> Self {
+        Self::new(Vec3::new(1.657, 0.880, 0.521), Vec3::new(9.224, 6.270, 4.837), roughness)
+    }
+    pub fn silver(roughness: f32) -> Self {
+        Self::new(Vec3::new(0.155, 0.117, 0.138), Vec3::new(4.828, 3.122, 2.147), roughness)
+    }
+}
+
+impl Material for Conductor {
+    /// The microfacet is picked among those visible from the ray and
+    /// the ray is mirrored on it. With that sampling the weight is
+    /// just Fresnel times the shadowing of the outgoing direction
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.reflect_on_microfacet(r_in, rec, None, attenuation, scattered)
+    }
+    /// A thin film is read at the wavelengths of the path
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: Vec3,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.reflect_on_microfacet(r_in, rec, Some(wavelengths), attenuation, scattered)
+    }
+    /// Fresnel * D * G2 / (4 cos(wo) cos(wi)), times cos(wi)
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Vec3 {
+        self.reflectance(r_in, rec, wi, None)
+    }
+    /// A thin film is read at the wavelengths of the path
+    fn eval_spectral(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3, wavelengths: Vec3) -> Vec3 {
+        self.reflectance(r_in, rec, wi, Some(wavelengths))
+    }
+    /// The density of the visible normal, times the Jacobian of the
+    /// reflection 1 / (4 wo.h)
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        let frame = Frame::from_tangent(rec.get_normal(), rec.get_tangents().0);
+        let wo = frame.to_local(r_in.get_direction().unit_vector() * -1.);
+        let wi = frame.to_local(wi);
+        if wo.z <= 0. || wi.z <= 0. {
+            return 0.;
+        }
+        let h = (wo + wi).unit_vector();
+        self.distribution.visible_d(wo, h) / (4. * wo.dot(h))
+    }
+}
+
+/// Frosted glass: like Dieletric, but the surface is made of tiny
+/// facets following the GGX distribution, which both reflect and
+/// refract. The roughness comes from a texture so it can change over
+/// the surface, e.g. for glass etched with a pattern
+pub struct RoughDielectric {
+    ref_idx: f32,
+    roughness: Arc<dyn Texture + Sync + Send>,
+    absorption: Vec3,
+}
+
+impl RoughDielectric {
+    pub fn new(ref_idx: f32, roughness: f32) -> Self {
+        Self::textured(ref_idx, Arc::new(SolidColor::scalar(roughness)))
+    }
+    pub fn textured(ref_idx: f32, roughness: Arc<dyn Texture + Sync + Send>) -> Self {
+        Self {
+            ref_idx,
+            roughness,
+            absorption: Vec3::new(0., 0., 0.),
+        }
+    }
+    /// See Dieletric::with_absorption()
+    pub fn with_absorption(mut self, absorption: Vec3) -> Self {
+        self.absorption = absorption;
+        self
+    }
+    /// See Dieletric::with_transmittance()
+    pub fn with_transmittance(self, color: Vec3, distance: f32) -> Self {
+        self.with_absorption(absorption_from_transmittance(color, distance))
+    }
+    fn distribution(&self, rec: &HitRecord) -> Ggx {
+        let r = rec.lookup(&*self.roughness).x;
+        Ggx::new(r, r)
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let mut rng = rand::thread_rng();
+        let frame = Frame::new(rec.get_normal());
+        let wo = frame.to_local(r_in.get_direction().unit_vector() * -1.);
+        if wo.z <= 0. {
+            return false;
+        }
+        let eta = if rec.get_front_face() {
+            self.ref_idx
+        } else {
+            1. / self.ref_idx
+        };
+        let distribution = self.distribution(rec);
+        let wi = distribution.sample_dielectric(wo, eta, rng.gen(), rng.gen(), rng.gen());
+        let (value, pdf) = distribution.dielectric(wo, wi, eta);
+        if pdf <= 0. {
+            return false;
+        }
+        let weight = value * wi.z.abs() / pdf;
+        *attenuation = absorption_transmittance(r_in, rec, self.absorption) * weight;
+        *scattered = Ray::new(rec.get_p(), frame.to_world(wi));
+        true
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Vec3 {
+        let frame = Frame::new(rec.get_normal());
+        let wo = frame.to_local(r_in.get_direction().unit_vector() * -1.);
+        let wi = frame.to_local(wi);
+        if wo.z <= 0. {
+            return Vec3::new(0., 0., 0.);
+        }
+        let eta = if rec.get_front_face() {
+            self.ref_idx
+        } else {
+            1. / self.ref_idx
+        };
+        let (value, _) = self.distribution(rec).dielectric(wo, wi, eta);
+        absorption_transmittance(r_in, rec, self.absorption) * (value * wi.z.abs())
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        let frame = Frame::new(rec.get_normal());
+        let wo = frame.to_local(r_in.get_direction().unit_vector() * -1.);
+        let wi = frame.to_local(wi);
+        if wo.z <= 0. {
+            return 0.;
+        }
+        let eta = if rec.get_front_face() {
+            self.ref_idx
+        } else {
+            1. / self.ref_idx
+        };
+        self.distribution(rec).dielectric(wo, wi, eta).1
+    }
+}
+
+// After this many trips between the base and the coat the light is
+// considered absorbed
+const MAX_COAT_BOUNCES: usize = 16;
+
+/// A thin transparent coat over another material, like varnish on wood
+/// or the clear layer of car paint. Light is reflected by the coat or
+/// goes through it following the Fresnel equations, is absorbed on its
+/// way to the base and back, and can bounce between the two several
+/// times before getting out. The coat is so thin that we ignore how far
+/// the light moves sideways in it. Only the reflection off the coat is
+/// lit by shadow rays, what goes through to the base is only found by
+/// scattering, so point, spot and directional lights do not light it
+pub struct Coated {
+    base: Arc<dyn Material + Sync + Send>,
+    ref_idx: f32,
+    roughness: f32,
+    thickness: f32,
+    absorption: Vec3,
+}
+
+impl Coated {
+    pub fn new(
+        base: Arc<dyn Material + Sync + Send>,
+        ref_idx: f32,
+        roughness: f32,
+        thickness: f32,
+    ) -> Self {
+        Self {
+            base,
+            ref_idx,
+            roughness,
+            thickness,
+            absorption: Vec3::new(0., 0., 0.),
+        }
+    }
+    /// See Dieletric::with_absorption()
+    pub fn with_absorption(mut self, absorption: Vec3) -> Self {
+        self.absorption = absorption;
+        self
+    }
+    /// See Dieletric::with_transmittance()
+    pub fn with_transmittance(self, color: Vec3, distance: f32) -> Self {
+        self.with_absorption(absorption_from_transmittance(color, distance))
+    }
+    // Light left after crossing the coat with the given cosine
+    fn transmittance(&self, cos_theta: f32) -> Vec3 {
+        let distance = self.thickness / cos_theta.abs().max(1e-4);
+        Vec3::new(
+            (-self.absorption.x * distance).exp(),
+            (-self.absorption.y * distance).exp(),
+            (-self.absorption.z * distance).exp(),
+        )
+    }
+    // What scatter() does on the outside, telling whether the light was
+    // reflected by the coat, the smooth lobe of eval(), or went down to
+    // the base, which is followed exactly and so is left to scattering
+    fn walk(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> Option<Lobe> {
+        let mut rng = rand::thread_rng();
+        let frame = Frame::new(rec.get_normal());
+        let wo = frame.to_local(-r_in.get_direction().unit_vector());
+        if wo.z <= 0. {
+            return None;
+        }
+        let coat = Ggx::new(self.roughness, self.roughness);
+        let mut weight = Vec3::new(1., 1., 1.);
+        // Every time the light meets the coat it is reflected or
+        // refracted, 'wo' always on the side of +z
+        let mut cross = |wo: Vec3, eta: f32, weight: &mut Vec3| {
+            let wi = coat.sample_dielectric(wo, eta, rng.gen(), rng.gen(), rng.gen());
+            let (value, pdf) = coat.dielectric(wo, wi, eta);
+            if pdf <= 0. {
+                return None;
+            }
+            *weight *= value * wi.z.abs() / pdf;
+            Some(wi)
+        };
+        let mut wi = cross(wo, self.ref_idx, &mut weight)?;
+        if wi.z > 0. {
+            *attenuation = weight;
+            *scattered = Ray::new(rec.get_p(), frame.to_world(wi));
+            return Some(Lobe::Smooth);
+        }
+        for _ in 0..MAX_COAT_BOUNCES {
+            // Down through the coat and off the base
+            weight *= self.transmittance(wi.z);
+            let down = Ray::new(rec.get_p(), frame.to_world(wi));
+            let mut base_attenuation = Vec3::new(0., 0., 0.);
+            let mut up = Ray::new(Vec3::new(0., 0., 0.), Vec3::new(0., 0., 0.));
+            if !self.base.scatter(&down, rec, &mut base_attenuation, &mut up) {
+                return None;
+            }
+            let up = frame.to_local(up.get_direction().unit_vector());
+            if up.z <= 0. {
+                return None;
+            }
+            weight *= base_attenuation * self.transmittance(up.z);
+            // Seen from below the coat, flipped so that we are on +z
+            let below = Vec3::new(-up.x, -up.y, up.z);
+            let from_below = cross(below, 1. / self.ref_idx, &mut weight)?;
+            wi = Vec3::new(from_below.x, from_below.y, -from_below.z);
+            if wi.z > 0. {
+                *attenuation = weight;
+                *scattered = Ray::new(rec.get_p(), frame.to_world(wi));
+                return Some(Lobe::Delta);
+            }
+        }
+        None
+    }
+}
+
+impl Material for Coated {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        // Inside the object there is no coat
+        if !rec.get_front_face() {
+            return self.base.scatter(r_in, rec, attenuation, scattered);
+        }
+        self.walk(r_in, rec, attenuation, scattered).is_some()
+    }
+    /// The light which went through the coat is a Delta lobe: eval()
+    /// and pdf() do not know about it, so it must not be shared with
+    /// the shadow rays
+    fn scatter_lobe(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: Option<Vec3>,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> Option<Lobe> {
+        if !rec.get_front_face() {
+            return self
+                .base
+                .scatter_lobe(r_in, rec, wavelengths, attenuation, scattered);
+        }
+        self.walk(r_in, rec, attenuation, scattered)
+    }
+    /// Only the reflection off the coat, see scatter_lobe()
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Vec3 {
+        if !rec.get_front_face() {
+            return self.base.eval(r_in, rec, wi);
+        }
+        let frame = Frame::new(rec.get_normal());
+        let wo = frame.to_local(-r_in.get_direction().unit_vector());
+        let wi = frame.to_local(wi);
+        if wo.z <= 0. || wi.z <= 0. {
+            return Vec3::new(0., 0., 0.);
+        }
+        let coat = Ggx::new(self.roughness, self.roughness);
+        let (value, _) = coat.dielectric(wo, wi, self.ref_idx);
+        Vec3::new(1., 1., 1.) * (value * wi.z)
+    }
+    fn eval_spectral(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3, wavelengths: Vec3) -> Vec3 {
+        if !rec.get_front_face() {
+            return self.base.eval_spectral(r_in, rec, wi, wavelengths);
+        }
+        rgb_to_spectrum(self.eval(r_in, rec, wi), wavelengths)
+    }
+    /// Already weighted by the chance of the coat reflecting the light
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        if !rec.get_front_face() {
+            return self.base.pdf(r_in, rec, wi);
+        }
+        let frame = Frame::new(rec.get_normal());
+        let wo = frame.to_local(-r_in.get_direction().unit_vector());
+        let wi = frame.to_local(wi);
+        if wo.z <= 0. || wi.z <= 0. {
+            return 0.;
+        }
+        let coat = Ggx::new(self.roughness, self.roughness);
+        coat.dielectric(wo, wi, self.ref_idx).1
+    }
+}
+
+/// Either of two materials, picked at random every time a ray hits it.
+/// The first channel of 'mask' is the chance of picking the second
+/// one, so a texture can put paint on some parts of a metal, or dirt.
+/// Any two materials can be mixed, but as they are picked again at
+/// every hit light may enter a glass and leave through something else
+pub struct MixMaterial {
+    first: Arc<dyn Material + Sync + Send>,
+    second: Arc<dyn Material + Sync + Send>,
+    mask: Arc<dyn Texture + Sync + Send>,
+}
+
+impl MixMaterial {
+    /// The same mix everywhere, 'weight' being the amount of 'second'
+    pub fn new(
+        first: Arc<dyn Material + Sync + Send>,
+        second: Arc<dyn Material + Sync + Send>,
+        weight: f32,
+    ) -> Self {
+        Self::masked(first, second, Arc::new(SolidColor::scalar(weight)))
+    }
+    pub fn masked(
+        first: Arc<dyn Material + Sync + Send>,
+        second: Arc<dyn Material + Sync + Send>,
+        mask: Arc<dyn Texture + Sync + Send>,
+    ) -> Self {
+        Self {
+            first,
+            second,
+            mask,
+        }
+    }
+    fn weight(&self, rec: &HitRecord) -> f32 {
+        rec.lookup(&*self.mask).x.clamp(0., 1.)
+    }
+    fn pick(&self, rec: &HitRecord) -> &Arc<dyn Material + Sync + Send> {
+        if rand::thread_rng().gen::<f32>() < self.weight(rec) {
+            &self.second
+        } else {
+            &self.first
+        }
+    }
+}
+
+impl Material for MixMaterial {
+    /// Picking each material with the probability of its weight, the
+    /// attenuation is the one it gives
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.pick(rec).scatter(r_in, rec, attenuation, scattered)
+    }
+    fn emitted(&self, rec: &HitRecord) -> Vec3 {
+        let w = self.weight(rec);
+        self.first.emitted(rec) * (1. - w) + self.second.emitted(rec) * w
+    }
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: Vec3,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.pick(rec)
+            .scatter_spectral(r_in, rec, wavelengths, attenuation, scattered)
+    }
+    /// The lobe of the material which was picked, eval() and pdf() would
+    /// not tell a mirror from the other material around it
+    fn scatter_lobe(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: Option<Vec3>,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> Option<Lobe> {
+        self.pick(rec)
+            .scatter_lobe(r_in, rec, wavelengths, attenuation, scattered)
+    }
+    fn opacity(&self, rec: &HitRecord) -> f32 {
+        let w = self.weight(rec);
+        self.first.opacity(rec) * (1. - w) + self.second.opacity(rec) * w
+    }
+    /// Only the smooth lobes count, mirrors and glass are black here
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Vec3 {
+        let w = self.weight(rec);
+        self.first.eval(r_in, rec, wi) * (1. - w) + self.second.eval(r_in, rec, wi) * w
+    }
+    fn eval_spectral(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3, wavelengths: Vec3) -> Vec3 {
+        let w = self.weight(rec);
+        self.first.eval_spectral(r_in, rec, wi, wavelengths) * (1. - w)
+            + self.second.eval_spectral(r_in, rec, wi, wavelengths) * w
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        let w = self.weight(rec);
+        self.first.pdf(r_in, rec, wi) * (1. - w) + self.second.pdf(r_in, rec, wi) * w
+    }
+}
+
+/// Cuts holes in another material, for leaves, fences and the like
+/// made of a single quad. The first channel of the mask is the
+/// opacity: rays go through where it is 0 and partly transparent spots
+/// let a part of them through at random. As shadow rays go through the
+/// same intersection, the holes also let light in
+pub struct AlphaCutout {
+    material: Arc<dyn Material + Sync + Send>,
+    mask: Arc<dyn Texture + Sync + Send>,
+}
+
+impl AlphaCutout {
+    pub fn new(
+        material: Arc<dyn Material + Sync + Send>,
+        mask: Arc<dyn Texture + Sync + Send>,
+    ) -> Self {
+        Self { material, mask }
+    }
+}
+
+impl Material for AlphaCutout {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.material.scatter(r_in, rec, attenuation, scattered)
+    }
+    fn emitted(&self, rec: &HitRecord) -> Vec3 {
+        self.material.emitted(rec)
+    }
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: Vec3,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.material
+            .scatter_spectral(r_in, rec, wavelengths, attenuation, scattered)
+    }
+    fn scatter_lobe(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: Option<Vec3>,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> Option<Lobe> {
+        self.material
+            .scatter_lobe(r_in, rec, wavelengths, attenuation, scattered)
+    }
+    fn opacity(&self, rec: &HitRecord) -> f32 {
+        let alpha = rec.lookup(&*self.mask).x;
+        alpha.clamp(0., 1.) * self.material.opacity(rec)
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Vec3 {
+        self.material.eval(r_in, rec, wi)
+    }
+    fn eval_spectral(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3, wavelengths: Vec3) -> Vec3 {
+        self.material.eval_spectral(r_in, rec, wi, wavelengths)
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        self.material.pdf(r_in, rec, wi)
+    }
+}
+
+// Step in u and v used to find the slope of a height map
+const BUMP_DELTA: f32 = 5e-4;
+
+// Where the shading normal of a BumpMap comes from
+enum Relief {
+    // Heights along the normal, multiplied by the scale
+    Height(Arc<dyn Texture + Sync + Send>, f32),
+    // Colors holding the normal in the tangent frame, each coordinate
+    // mapped from [-1,1] to [0,1] as usual
+    Normals(Arc<dyn Texture + Sync + Send>),
+}
+
+/// Adds detail to another material by changing the shading normal, so
+/// the surface looks bumpy while its shape stays the same. The
+/// geometric normal still decides which side the ray came from
+pub struct BumpMap {
+    material: Arc<dyn Material + Sync + Send>,
+    relief: Relief,
+}
+
+impl BumpMap {
+    /// Bumps from the first channel of a height map, raised along the
+    /// normal by 'scale' times its value
+    pub fn new(
+        material: Arc<dyn Material + Sync + Send>,
+        height: Arc<dyn Texture + Sync + Send>,
+        scale: f32,
+    ) -> Self {
+        Self {
+            material,
+            relief: Relief::Height(height, scale),
+        }
+    }
+    /// Bumps from a tangent space normal map, where x goes along u, y
+    /// along v and z out of the surface
+    pub fn from_normal_map(
+        material: Arc<dyn Material + Sync + Send>,
+        normals: Arc<dyn Texture + Sync + Send>,
+    ) -> Self {
+        Self {
+            material,
+            relief: Relief::Normals(normals),
+        }
+    }
+    // The record seen by the wrapped material
+    fn bumped(&self, rec: &HitRecord) -> HitRecord {
+        let n = rec.get_outward_normal();
+        let (dpdu, dpdv) = rec.get_tangents();
+        let (u, v, p) = (rec.get_u(), rec.get_v(), rec.get_p());
+        let bumped = match &self.relief {
+            Relief::Height(height, scale) => {
+                let h = |du: f32, dv: f32| {
+                    let moved = p + dpdu * du + dpdv * dv;
+                    height.value(u + du, v + dv, &moved).x * scale
+                };
+                let h0 = h(0., 0.);
+                let dhdu = (h(BUMP_DELTA, 0.) - h0) / BUMP_DELTA;
+                let dhdv = (h(0., BUMP_DELTA) - h0) / BUMP_DELTA;
+                // The tangents of the displaced surface p + h * n,
+                // their product faces n when the bumps are flat
+                let side = dpdu.cross(dpdv).dot(n).signum();
+                (dpdu + n * dhdu).cross(dpdv + n * dhdv) * side
+            }
+            Relief::Normals(normals) => {
+                let m = rec.lookup(&**normals) * 2. - Vec3::new(1., 1., 1.);
+                let t = (dpdu - n * n.dot(dpdu)).unit_vector();
+                let mut b = n.cross(t);
+                if b.dot(dpdv) < 0. {
+                    b = -b;
+                }
+                t * m.x + b * m.y + n * m.z
+            }
+        };
+        let mut bumped = bumped.unit_vector();
+        if bumped.dot(n) < 0. {
+            bumped = -bumped;
+        }
+        let mut rec = rec.clone();
+        rec.set_shading_normal(if rec.get_front_face() {
+            bumped
+        } else {
+            -bumped
+        });
+        rec
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.material
+            .scatter(r_in, &self.bumped(rec), attenuation, scattered)
+    }
+    fn emitted(&self, rec: &HitRecord) -> Vec3 {
+        self.material.emitted(rec)
+    }
+    fn scatter_spectral(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: Vec3,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.material
+            .scatter_spectral(r_in, &self.bumped(rec), wavelengths, attenuation, scattered)
+    }
+    fn scatter_lobe(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        wavelengths: Option<Vec3>,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> Option<Lobe> {
+        self.material
+            .scatter_lobe(r_in, &self.bumped(rec), wavelengths, attenuation, scattered)
+    }
+    fn opacity(&self, rec: &HitRecord) -> f32 {
+        self.material.opacity(rec)
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Vec3 {
+        self.material.eval(r_in, &self.bumped(rec), wi)
+    }
+    fn eval_spectral(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3, wavelengths: Vec3) -> Vec3 {
+        self.material
+            .eval_spectral(r_in, &self.bumped(rec), wi, wavelengths)
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        self.material.pdf(r_in, &self.bumped(rec), wi)
+    }
+}
@@ -0,0 +1,336 @@
+use crate::vecmath::Vec3;
+use std::f32::consts::PI;
+use std::ops::{Add, Div, Mul, Sub};
+
+/// An orthonormal basis around a normal, the microfacet formulas are
+/// written in this local space where the normal is +z
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    t: Vec3,
+    b: Vec3,
+    n: Vec3,
+}
+
+impl Frame {
+    /// 'n' must be a unit vector
+    pub fn new(n: Vec3) -> Self {
+        let (t, b) = n.orthonormal_basis();
+        Self { t, b, n }
+    }
+    /// Same as new(), with the first axis following 'tangent' projected
+    /// onto the plane of 'n', so anisotropic lobes line up with the
+    /// surface instead of turning with the normal
+    pub fn from_tangent(n: Vec3, tangent: Vec3) -> Self {
+        let t = tangent - n * n.dot(tangent);
+        if t.squared_len() < 1e-12 {
+            return Self::new(n);
+        }
+        let t = t.unit_vector();
+        Self { t, b: n.cross(t), n }
+    }
+    pub fn to_local(self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.t), v.dot(self.b), v.dot(self.n))
+    }
+    pub fn to_world(self, v: Vec3) -> Vec3 {
+        self.t * v.x + self.b * v.y + self.n * v.z
+    }
+}
+
+/// Fraction of light reflected by a metal, with complex index of
+/// refraction eta + i*k, for each color channel
+pub fn fresnel_conductor(cos_theta: f32, eta: Vec3, k: Vec3) -> Vec3 {
+    let channel = |eta: f32, k: f32| {
+        let cos2 = cos_theta.clamp(0., 1.).powi(2);
+        let sin2 = 1. - cos2;
+        let eta2 = eta * eta;
+        let k2 = k * k;
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4. * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+        let t2 = 2. * cos_theta * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rp + rs)
+    };
+    Vec3::new(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+/// Fraction of light reflected by the boundary between two transparent
+/// materials, 'eta' is the index of refraction of the side the light
+/// goes into divided by the one it comes from
+pub fn fresnel_dielectric(cos_theta: f32, eta: f32) -> f32 {
+    let cos_i = cos_theta.clamp(0., 1.);
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    // Total internal reflection
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.
+}
+
+// Just what the thin film needs of complex numbers
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Add for Complex {
+    type Output = Self;
+    fn add(self, o: Self) -> Self {
+        Self::new(self.re + o.re, self.im + o.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+    fn sub(self, o: Self) -> Self {
+        Self::new(self.re - o.re, self.im - o.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+    fn mul(self, o: Self) -> Self {
+        Self::new(
+            self.re * o.re - self.im * o.im,
+            self.re * o.im + self.im * o.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+    fn div(self, o: Self) -> Self {
+        let d = o.norm();
+        Self::new(
+            (self.re * o.re + self.im * o.im) / d,
+            (self.im * o.re - self.re * o.im) / d,
+        )
+    }
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+    // Squared modulus
+    fn norm(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+    // The root with a positive real part
+    fn sqrt(self) -> Self {
+        let r = self.norm().sqrt();
+        let re = ((r + self.re) / 2.).max(0.).sqrt();
+        let im = ((r - self.re) / 2.).max(0.).sqrt();
+        Self::new(re, if self.im < 0. { -im } else { im })
+    }
+    fn exp_i(phase: Complex) -> Self {
+        // e^(i * phase)
+        let magnitude = (-phase.im).exp();
+        Self::new(magnitude * phase.re.cos(), magnitude * phase.re.sin())
+    }
+}
+
+// Fresnel amplitude coefficients (s and p) from the medium 'n1' into
+// 'n2', with the cosines of the angles on both sides
+fn fresnel_amplitudes(n1: Complex, cos1: Complex, n2: Complex, cos2: Complex) -> (Complex, Complex) {
+    let (a, b) = (n1 * cos1, n2 * cos2);
+    let rs = (a - b) / (a + b);
+    let (c, d) = (n2 * cos1, n1 * cos2);
+    let rp = (c - d) / (c + d);
+    (rs, rp)
+}
+
+// Cosine of the angle in the medium 'n', from Snell's law
+fn snell_cosine(n: Complex, n_sin: f32) -> Complex {
+    let sin = Complex::new(n_sin, 0.) / n;
+    (Complex::new(1., 0.) - sin * sin).sqrt()
+}
+
+/// Fraction of light of wavelength 'lambda' (nanometers) reflected by a
+/// film of index 'film' and 'thickness' nanometers over a material of
+/// complex index 'eta' + i * 'k' (0 for dielectrics), seen from a
+/// medium of index 'outside'. The waves reflected on the two sides of
+/// the film add up or cancel out depending on how much longer one
+/// travels, which is what makes soap bubbles colorful (Airy's formula)
+pub fn fresnel_thin_film(
+    cos_theta: f32,
+    outside: f32,
+    film: f32,
+    thickness: f32,
+    eta: f32,
+    k: f32,
+    lambda: f32,
+) -> f32 {
+    let cos_theta = cos_theta.clamp(0., 1.);
+    let n1 = Complex::new(outside, 0.);
+    let n2 = Complex::new(film, 0.);
+    let n3 = Complex::new(eta, k);
+    // n * sin(theta) is the same in every layer
+    let n_sin = outside * (1. - cos_theta * cos_theta).sqrt();
+    let cos1 = Complex::new(cos_theta, 0.);
+    let cos2 = snell_cosine(n2, n_sin);
+    let cos3 = snell_cosine(n3, n_sin);
+    let (r12s, r12p) = fresnel_amplitudes(n1, cos1, n2, cos2);
+    let (r23s, r23p) = fresnel_amplitudes(n2, cos2, n3, cos3);
+    // Difference of phase between two waves coming out of the film
+    let delta = n2 * cos2 * Complex::new(4. * PI * thickness / lambda, 0.);
+    let phase = Complex::exp_i(delta);
+    let total = |r12: Complex, r23: Complex| {
+        let r23 = r23 * phase;
+        ((r12 + r23) / (Complex::new(1., 0.) + r12 * r23)).norm()
+    };
+    ((total(r12s, r23s) + total(r12p, r23p)) / 2.).clamp(0., 1.)
+}
+
+/// Direction of 'wo' refracted through a surface with normal 'n', on
+/// the same side as 'wo', both pointing away from the surface.
+/// None when there is total internal reflection
+pub fn refract_through(wo: Vec3, n: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = wo.dot(n);
+    let sin2_t = (1. - cos_i * cos_i).max(0.) / (eta * eta);
+    if sin2_t >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some(wo * (-1. / eta) + n * (cos_i / eta - cos_t))
+}
+
+/// The GGX (Trowbridge-Reitz) distribution of microfacet normals, with
+/// a different roughness along the two tangents when anisotropic.
+/// Every direction here is in the local space of a Frame
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    alpha_x: f32,
+    alpha_y: f32,
+}
+
+impl Ggx {
+    /// Roughness goes from 0 (mirror) to 1, alpha = roughness^2 makes
+    /// it look more linear
+    pub fn new(roughness_x: f32, roughness_y: f32) -> Self {
+        // Below this the numbers get too close to a delta
+        let alpha = |r: f32| (r * r).max(1e-3);
+        Self {
+            alpha_x: alpha(roughness_x),
+            alpha_y: alpha(roughness_y),
+        }
+    }
+    /// Density of the microfacets with normal 'm'
+    pub fn d(&self, m: Vec3) -> f32 {
+        if m.z <= 0. {
+            return 0.;
+        }
+        let e = (m.x / self.alpha_x).powi(2) + (m.y / self.alpha_y).powi(2) + m.z * m.z;
+        1. / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+    fn lambda(&self, w: Vec3) -> f32 {
+        if w.z == 0. {
+            return f32::MAX;
+        }
+        let a2 = (self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2);
+        // Only the angle matters, not the side
+        (-1. + (1. + a2 / (w.z * w.z)).sqrt()) / 2.
+    }
+    /// Smith masking: the fraction of microfacets visible from 'w'
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1. / (1. + self.lambda(w))
+    }
+    /// Height correlated Smith shadowing-masking
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+    /// Density of the normals visible from 'wo', which is what
+    /// sample_visible_normal() picks from
+    pub fn visible_d(&self, wo: Vec3, m: Vec3) -> f32 {
+        if wo.z <= 0. {
+            return 0.;
+        }
+        self.g1(wo) * wo.dot(m).max(0.) * self.d(m) / wo.z
+    }
+    /// Picks a microfacet normal among those visible from 'wo' (Heitz
+    /// 2018): the view is stretched so the distribution becomes a
+    /// hemisphere, a point is picked on its projection, and the result
+    /// is stretched back. No samples are wasted on hidden microfacets
+    pub fn sample_visible_normal(&self, wo: Vec3, u1: f32, u2: f32) -> Vec3 {
+        let vh = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).unit_vector();
+        let lensq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if lensq > 0. {
+            Vec3::new(-vh.y, vh.x, 0.) / lensq.sqrt()
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let t2 = vh.cross(t1);
+        let r = u1.sqrt();
+        let phi = 2. * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1. + vh.z);
+        let p2 = (1. - s) * (1. - p1 * p1).max(0.).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1. - p1 * p1 - p2 * p2).max(0.).sqrt();
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).unit_vector()
+    }
+    /// Value of the BSDF of a rough boundary between two transparent
+    /// materials and probability density of scattering from 'wo' to
+    /// 'wi', as picked by sample_dielectric(). 'wo' is on the side of
+    /// the normal, 'eta' is the index of refraction of the other side
+    /// over the one of this side. The density of a refracted direction
+    /// includes the Jacobian of the change of variables from the
+    /// microfacet normal to the refracted direction (Walter et al. 2007).
+    /// As in Dieletric, the (1/eta)^2 scaling of radiance when crossing
+    /// is left out, it cancels when the ray goes in and out again
+    pub fn dielectric(&self, wo: Vec3, wi: Vec3, eta: f32) -> (f32, f32) {
+        if wo.z <= 0. || wi.z == 0. {
+            return (0., 0.);
+        }
+        let reflect = wi.z > 0.;
+        let etap = if reflect { 1. } else { eta };
+        // The microfacet normal which sends wo to wi
+        let mut m = wi * etap + wo;
+        if m.squared_len() == 0. {
+            return (0., 0.);
+        }
+        m = m.unit_vector();
+        if m.z < 0. {
+            m = -m;
+        }
+        // Microfacets seen from behind cannot do it
+        if m.dot(wi) * wi.z < 0. || m.dot(wo) < 0. {
+            return (0., 0.);
+        }
+        let f = fresnel_dielectric(wo.dot(m), eta);
+        if reflect {
+            let value = self.d(m) * self.g2(wo, wi) * f / (4. * wo.z * wi.z);
+            let pdf = self.visible_d(wo, m) / (4. * wo.dot(m)) * f;
+            (value, pdf)
+        } else {
+            let denom = (wi.dot(m) + wo.dot(m) / eta).powi(2);
+            let value = self.d(m) * self.g2(wo, wi) * (1. - f)
+                * (wi.dot(m) * wo.dot(m) / (denom * wi.z * wo.z)).abs();
+            let dm_dwi = wi.dot(m).abs() / denom;
+            let pdf = self.visible_d(wo, m) * dm_dwi * (1. - f);
+            (value, pdf)
+        }
+    }
+    /// A visible microfacet is picked, then like in Dieletric the ray is
+    /// reflected with the probability given by Fresnel or refracted
+    pub fn sample_dielectric(&self, wo: Vec3, eta: f32, u1: f32, u2: f32, u3: f32) -> Vec3 {
+        let m = self.sample_visible_normal(wo, u1, u2);
+        let reflected = m * (2. * wo.dot(m)) - wo;
+        if u3 < fresnel_dielectric(wo.dot(m), eta) {
+            reflected
+        } else {
+            refract_through(wo, m, eta).unwrap_or(reflected)
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::materials::*;
 use crate::hitables::*;
+use crate::spectrum::*;
 use crate::vecmath::Vec3;
 use rand::Rng;
 use std::sync::Arc;
@@ -12,12 +13,32 @@ use std::sync::Arc;
 pub struct Ray {
     origin: Vec3,
     direction: Vec3,
+    differentials: Option<RayDifferentials>,
+}
+
+/// The rays through the next pixel to the right (x) and the one above
+/// (y), traced along with a camera ray to know how big a pixel is
+/// where it hits and so how much of a texture it covers
+#[derive(Debug, Clone, Copy)]
+pub struct RayDifferentials {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3,
 }
 
 impl Ray {
     /// Create a new ray given 'a' starting position and 'b' direction
     pub fn new(origin: Vec3, direction: Vec3) -> Self {
-        Self { origin, direction }
+        Self {
+            origin,
+            direction,
+            differentials: None,
+        }
+    }
+    pub fn with_differentials(mut self, differentials: RayDifferentials) -> Self {
+        self.differentials = Some(differentials);
+        self
     }
     /// Position at certain 't' parameter
     pub fn point_at_parameter(&self, t: f32) -> Vec3 {
@@ -31,6 +52,11 @@ impl Ray {
     pub fn get_direction(&self) -> Vec3 {
         self.direction
     }
+
+    /// Only camera rays have them, scattered rays start without
+    pub fn get_differentials(&self) -> Option<RayDifferentials> {
+        self.differentials
+    }
 }
 
 impl Vec3 {
@@ -48,6 +74,21 @@ impl Vec3 {
         }
     }
 
+    // A random direction, every direction is equally likely
+    pub fn random_unit_vector() -> Self {
+        Vec3::random_in_unit_sphere().unit_vector()
+    }
+
+    // A direction around +z, more likely the closer it is to it, with
+    // a density of cos(theta) / pi
+    pub fn random_cosine_direction() -> Self {
+        let mut rng = rand::thread_rng();
+        let r1: f32 = rng.gen();
+        let phi = 2. * std::f32::consts::PI * rng.gen::<f32>();
+        let r = r1.sqrt();
+        Vec3::new(r * phi.cos(), r * phi.sin(), (1. - r1).sqrt())
+    }
+
     // We need this to simulate the focus and blur of the image
     pub fn random_in_unit_disc() -> Self {
         let mut p: Vec3;
@@ -75,32 +116,146 @@ impl Vec3 {
     //         }
     //     }
 
-    pub fn color_material(r: &Ray, world: &HitableList, depth: i32) -> Vec3 {
+    /// The light of the lights, and of the sky when it can be sampled,
+    /// sent back along 'r' from its hit. The lights without bounds are
+    /// all looked at, of the others a single one is picked from the
+    /// LightTree. Each light is checked with a shadow ray, which goes
+    /// through the holes of opacity masks like any ray
+    fn direct_light(
+        r: &Ray,
+        rec: &HitRecord,
+        world: &HitableList,
+        wavelengths: Option<Vec3>,
+        spectrum: &impl Fn(Vec3) -> Vec3,
+    ) -> Vec3 {
+        let mut total = Vec3::new(0., 0., 0.);
+        let mut blocker = rec.clone();
+        let p = rec.get_p();
+        // Along with the probability of picking the light
+        let picked = world
+            .lights
+            .sample(p)
+            .and_then(|(light, pmf)| Some((light.sample(p)?, pmf)));
+        let samples = world
+            .lights
+            .infinite()
+            .map(|light| light.sample(p).map(|sample| (sample, 1.)))
+            .chain(std::iter::once(picked))
+            .chain(world.sky.iter().map(|sky| sky.sample().map(|sample| (sample, 1.))));
+        for (sample, pmf) in samples.flatten() {
+            let f = rec.eval(r, sample.direction, wavelengths);
+            if f.x <= 0. && f.y <= 0. && f.z <= 0. {
+                continue;
+            }
+            let shadow = Ray::new(p, sample.direction);
+            if world.hit_list(&shadow, 0.001, sample.distance * 0.999, &mut blocker) {
+                continue;
+            }
+            let weight = mis_weight(sample.pdf * pmf, rec.pdf(r, sample.direction));
+            total += f * spectrum(sample.radiance / pmf) * weight;
+        }
+        total
+    }
+
+    /// The light coming along the ray. With 'wavelengths' we are in
+    /// spectral mode and the light is given at each of them, every
+    /// color met along the way is turned into a spectrum
+    pub fn color_material(
+        r: &Ray,
+        world: &HitableList,
+        wavelengths: Option<Vec3>,
+        depth: i32,
+    ) -> Vec3 {
+        Vec3::trace(r, world, wavelengths, depth, None)
+    }
+
+    // 'scatter_pdf' is given when the ray was scattered by a material
+    // which also looked for the lights with shadow rays, it is the
+    // density of the material picking the direction of the ray. A
+    // light the ray finds is then weighted against the shadow rays so
+    // it is not counted twice
+    fn trace(
+        r: &Ray,
+        world: &HitableList,
+        wavelengths: Option<Vec3>,
+        depth: i32,
+        scatter_pdf: Option<f32>,
+    ) -> Vec3 {
+        let spectrum = |c: Vec3| match wavelengths {
+            Some(lambda) => rgb_to_spectrum(c, lambda),
+            None => c,
+        };
         let mut rec: HitRecord = HitRecord::new(
             0.,
             Vec3::new(0., 0., 0.),
             Vec3::new(0., 0., 0.),
             Arc::new(Lambertian::new(Vec3::new(0., 0., 0.))),
         );
+        let weight = |light_pdf: f32| match scatter_pdf {
+            Some(pdf) => mis_weight(pdf, light_pdf),
+            None => 1.,
+        };
         // To prevent shadow acne, try setting it to other values
-        if world.hit_list(&r, 0.001, f32::MAX, &mut rec) {
+        let hit = world.hit_list(r, 0.001, f32::MAX, &mut rec);
+        // Lights with a size are not part of the geometry, but they are
+        // seen when nothing is in front of them
+        let t_max = if hit { rec.get_t() } else { f32::MAX };
+        if let Some((index, radiance)) = world.lights.hit(r, 0.001, t_max) {
+            let (origin, direction) = (r.get_origin(), r.get_direction().unit_vector());
+            let light_pdf =
+                world.lights.pmf(origin, index) * world.lights.get(index).pdf(origin, direction);
+            return spectrum(radiance) * weight(light_pdf);
+        }
+        if hit {
             let mut scattered = Ray::new(Vec3::new(0., 0., 0.), Vec3::new(0., 0., 0.));
             let mut attenuation = Vec3::new(0., 0., 0.);
-            if depth < 50 && rec.scatter(r, &mut attenuation, &mut scattered) {
-                return attenuation * Vec3::color_material(&scattered, world, depth + 1);
-            } else {
-                return Vec3::new(0., 0., 0.);
+            let emitted = spectrum(rec.emitted())
+                + Vec3::direct_light(r, &rec, world, wavelengths, &spectrum);
+            match rec.scatter_lobe(r, wavelengths, &mut attenuation, &mut scattered) {
+                Some(lobe) if depth < 50 => {
+                    // Smooth lobes also got the light from there with
+                    // the shadow rays
+                    let pdf = match lobe {
+                        Lobe::Smooth => Some(rec.pdf(r, scattered.get_direction().unit_vector())),
+                        Lobe::Delta => None,
+                    };
+                    emitted
+                        + attenuation * Vec3::trace(&scattered, world, wavelengths, depth + 1, pdf)
+                }
+                _ => emitted,
             }
         } else {
-            // Linearly blend blue and white based on the direction y coordinate
-            // High y = blue, Low y = white. This is called linear interpolation
-            let unit = r.direction.unit_vector();
-            let t = (unit.y + 1.) * 0.5;
-            Vec3::new(1., 1., 1.) * (1. - t) + Vec3::new(0.5, 0.7, 1.) * t
+            let mut background = match &world.sky {
+                Some(sky) => sky.radiance(r.direction) * weight(sky.pdf(r.direction)),
+                None => {
+                    // Linearly blend blue and white based on the direction y coordinate
+                    // High y = blue, Low y = white. This is called linear interpolation
+                    let unit = r.direction.unit_vector();
+                    let t = (unit.y + 1.) * 0.5;
+                    Vec3::new(1., 1., 1.) * (1. - t) + Vec3::new(0.5, 0.7, 1.) * t
+                }
+            };
+            for light in world.lights.infinite() {
+                let light_pdf = light.pdf(r.get_origin(), r.direction);
+                background += light.emitted(r.direction) * weight(light_pdf);
+            }
+            spectrum(background)
         }
     }
 }
 
+// The power heuristic (Veach 1997): the share of the light found by a
+// sampling technique of density 'pdf' when another one of density
+// 'other' could have found it as well. Whatever only one of them can
+// find belongs to it completely
+fn mis_weight(pdf: f32, other: f32) -> f32 {
+    if other <= 0. || pdf.is_infinite() {
+        return 1.;
+    }
+    let (a, b) = (pdf * pdf, other * other);
+    a / (a + b)
+}
+
 /// Transform a color vector in u32
 impl From<Vec3> for u32 {
     fn from(t: Vec3) -> Self {
@@ -120,6 +275,9 @@ pub struct Camera {
     v: Vec3,
     w: Vec3,
     lens_radius: f32,
+    // The size of a pixel in the s and t given to get_ray(), when
+    // known the rays carry differentials
+    pixel_size: Option<(f32, f32)>,
 }
 
 impl Camera {
@@ -152,15 +310,34 @@ impl Camera {
             v,
             w,
             lens_radius,
+            pixel_size: None,
         }
     }
+    /// The size of the image in pixels, lets textures be filtered
+    /// over the area a pixel covers instead of aliasing far away
+    pub fn with_resolution(mut self, width: usize, height: usize) -> Self {
+        self.pixel_size = Some((1. / width as f32, 1. / height as f32));
+        self
+    }
     pub fn get_ray(&self, s: f32, t: f32) -> Ray {
         let rd = Vec3::random_in_unit_disc() * self.lens_radius;
         let offset = self.u * rd.x + self.v * rd.y;
-        Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset,
-        )
+        let origin = self.origin + offset;
+        let direction_at = |s: f32, t: f32| {
+            self.lower_left_corner + self.horizontal * s + self.vertical * t - origin
+        };
+        let ray = Ray::new(origin, direction_at(s, t));
+        match self.pixel_size {
+            // Through the same point of the lens, still in focus at
+            // the focus distance
+            Some((ds, dt)) => ray.with_differentials(RayDifferentials {
+                rx_origin: origin,
+                rx_direction: direction_at(s + ds, t),
+                ry_origin: origin,
+                ry_direction: direction_at(s, t + dt),
+            }),
+            None => ray,
+        }
     }
 }
 
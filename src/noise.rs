@@ -0,0 +1,135 @@
+use crate::vecmath::Vec3;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+// Ken Perlin's smooth step, with zero first and second derivatives at
+// 0 and 1 so the noise has no visible creases along the lattice
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+// Dot product of (x, y, z) with one of the 12 directions to the
+// middle of the edges of a cube, picked by the hash
+fn grad(hash: usize, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// Lattice noise built from a shuffled table, the same seed always
+/// gives the same noise
+pub struct Noise {
+    // The permutation of 0..256 written twice, so indices can go past
+    // 255 without wrapping
+    perm: [usize; 512],
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        let mut table: Vec<usize> = (0..256).collect();
+        table.shuffle(&mut StdRng::seed_from_u64(seed));
+        let mut perm = [0; 512];
+        for (i, p) in perm.iter_mut().enumerate() {
+            *p = table[i & 255];
+        }
+        Self { perm }
+    }
+
+    // A number in 0..256 for each lattice point
+    fn hash(&self, i: i32, j: i32, k: i32) -> usize {
+        let a = self.perm[(i & 255) as usize] + (j & 255) as usize;
+        self.perm[self.perm[a] + (k & 255) as usize]
+    }
+
+    /// Improved Perlin noise (Perlin 2002), smooth and roughly in
+    /// [-1,1], zero on every point of the integer lattice
+    pub fn perlin(&self, p: Vec3) -> f32 {
+        let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (i, j, k) = (fx as i32, fy as i32, fz as i32);
+        let (x, y, z) = (p.x - fx, p.y - fy, p.z - fz);
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+        let corner = |di: i32, dj: i32, dk: i32| {
+            grad(
+                self.hash(i + di, j + dj, k + dk),
+                x - di as f32,
+                y - dj as f32,
+                z - dk as f32,
+            )
+        };
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+                lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+            ),
+            lerp(
+                v,
+                lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+                lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+            ),
+        )
+    }
+
+    // Octaves of 'f', each twice the frequency and half the amplitude
+    // of the previous one, divided by the sum of the amplitudes
+    fn octaves(&self, p: Vec3, octaves: u32, f: impl Fn(f32) -> f32) -> f32 {
+        let (mut sum, mut total) = (0., 0.);
+        let (mut frequency, mut amplitude) = (1., 1.);
+        for _ in 0..octaves.max(1) {
+            sum += amplitude * f(self.perlin(p * frequency));
+            total += amplitude;
+            frequency *= 2.;
+            amplitude *= 0.5;
+        }
+        sum / total
+    }
+
+    /// Fractal Brownian motion: noise with details at every scale,
+    /// in [-1,1]
+    pub fn fbm(&self, p: Vec3, octaves: u32) -> f32 {
+        self.octaves(p, octaves, |n| n)
+    }
+
+    /// Like fbm() but adding the absolute values, which leaves sharp
+    /// creases where the noise crosses zero. In [0,1]
+    pub fn turbulence(&self, p: Vec3, octaves: u32) -> f32 {
+        self.octaves(p, octaves, f32::abs)
+    }
+
+    /// Cellular noise (Worley 1996): the distance to the closest of a
+    /// set of points scattered one in each cell of the lattice
+    pub fn worley(&self, p: Vec3) -> f32 {
+        let (i, j, k) = (p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32);
+        let mut closest = f32::MAX;
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let (ci, cj, ck) = (i + di, j + dj, k + dk);
+                    // Three hashes from the one of the cell
+                    let h = self.hash(ci, cj, ck);
+                    let offset = Vec3::new(
+                        h as f32,
+                        self.perm[h + 1] as f32,
+                        self.perm[self.perm[h + 2] + 3] as f32,
+                    ) / 256.;
+                    let feature = Vec3::new(ci as f32, cj as f32, ck as f32) + offset;
+                    closest = closest.min((feature - p).squared_len());
+                }
+            }
+        }
+        closest.sqrt()
+    }
+}
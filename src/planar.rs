@@ -0,0 +1,282 @@
+use crate::hitables::*;
+use crate::lights::LightTree;
+use crate::materials::*;
+use crate::mylib::*;
+use crate::vecmath::Vec3;
+use std::sync::Arc;
+
+// Intersection of the ray with the plane through 'point' with normal
+// 'n', returns the t parameter when it is inside (t_min, t_max)
+fn hit_plane(r: &Ray, point: &Vec3, n: &Vec3, t_min: f32, t_max: f32) -> Option<f32> {
+    let denom = n.dot(r.get_direction());
+    // The ray is parallel to the plane
+    if denom.abs() < 1e-8 {
+        return None;
+    }
+    let t = (*point - r.get_origin()).dot(*n) / denom;
+    if t < t_max && t > t_min {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+/// A plane with no edges, useful as a floor instead of a huge sphere
+pub struct Plane {
+    point: Vec3,
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    material: Arc<dyn Material + Sync + Send>,
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, material: Arc<dyn Material + Sync + Send>) -> Self {
+        let normal = normal.unit_vector();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        Self {
+            point,
+            normal,
+            tangent,
+            bitangent,
+            material,
+        }
+    }
+}
+
+impl Hitable for Plane {
+    /// The u,v coordinates are the distances from 'point' along the
+    /// two tangents, so they are not limited to [0,1] and a texture
+    /// that repeats will tile the plane
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        match hit_plane(r, &self.point, &self.normal, t_min, t_max) {
+            Some(t) => {
+                let p = r.point_at_parameter(t);
+                let d = p - self.point;
+                *rec = HitRecord::new(t, p, self.normal, self.material.clone());
+                rec.set_face_normal(r, &self.normal);
+                rec.set_uv(d.dot(self.tangent), d.dot(self.bitangent));
+                rec.set_tangents(self.tangent, self.bitangent);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A parallelogram with a corner in 'q' and sides 'u' and 'v',
+/// the front face is the one 'u' x 'v' points to
+pub struct Quad {
+    q: Vec3,
+    u: Vec3,
+    v: Vec3,
+    // Used to find the planar coordinates of a point on the quad
+    w: Vec3,
+    normal: Vec3,
+    material: Arc<dyn Material + Sync + Send>,
+}
+
+impl Quad {
+    pub fn new(q: Vec3, u: Vec3, v: Vec3, material: Arc<dyn Material + Sync + Send>) -> Self {
+        let n = u.cross(v);
+        Self {
+            q,
+            u,
+            v,
+            w: n / n.dot(n),
+            normal: n.unit_vector(),
+            material,
+        }
+    }
+    /// Rectangle on the plane z = k, facing +z
+    pub fn xy_rect(
+        x0: f32,
+        x1: f32,
+        y0: f32,
+        y1: f32,
+        k: f32,
+        material: Arc<dyn Material + Sync + Send>,
+    ) -> Self {
+        Self::new(
+            Vec3::new(x0, y0, k),
+            Vec3::new(x1 - x0, 0., 0.),
+            Vec3::new(0., y1 - y0, 0.),
+            material,
+        )
+    }
+    /// Rectangle on the plane y = k, facing +y
+    pub fn xz_rect(
+        x0: f32,
+        x1: f32,
+        z0: f32,
+        z1: f32,
+        k: f32,
+        material: Arc<dyn Material + Sync + Send>,
+    ) -> Self {
+        Self::new(
+            Vec3::new(x0, k, z0),
+            Vec3::new(0., 0., z1 - z0),
+            Vec3::new(x1 - x0, 0., 0.),
+            material,
+        )
+    }
+    /// Rectangle on the plane x = k, facing +x
+    pub fn yz_rect(
+        y0: f32,
+        y1: f32,
+        z0: f32,
+        z1: f32,
+        k: f32,
+        material: Arc<dyn Material + Sync + Send>,
+    ) -> Self {
+        Self::new(
+            Vec3::new(k, y0, z0),
+            Vec3::new(0., y1 - y0, 0.),
+            Vec3::new(0., 0., z1 - z0),
+            material,
+        )
+    }
+}
+
+impl Hitable for Quad {
+    /// The planar coordinates of the hit point along 'u' and 'v' are
+    /// both in [0,1] only inside the quad, and they are also its u,v
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        match hit_plane(r, &self.q, &self.normal, t_min, t_max) {
+            Some(t) => {
+                let p = r.point_at_parameter(t);
+                let planar = p - self.q;
+                let alpha = self.w.dot(planar.cross(self.v));
+                let beta = self.w.dot(self.u.cross(planar));
+                if !(0. ..=1.).contains(&alpha) || !(0. ..=1.).contains(&beta) {
+                    return false;
+                }
+                *rec = HitRecord::new(t, p, self.normal, self.material.clone());
+                rec.set_face_normal(r, &self.normal);
+                rec.set_uv(alpha, beta);
+                rec.set_tangents(self.u, self.v);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Slightly thicker than the quad, so that it is not flat
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(
+            Aabb::from_points(&[self.q, self.q + self.u, self.q + self.v, self.q + self.u + self.v])
+                .grow(1e-4),
+        )
+    }
+}
+
+/// A flat circle centered in 'center'
+pub struct Disk {
+    center: Vec3,
+    normal: Vec3,
+    radius: f32,
+    tangent: Vec3,
+    bitangent: Vec3,
+    material: Arc<dyn Material + Sync + Send>,
+}
+
+impl Disk {
+    pub fn new(
+        center: Vec3,
+        normal: Vec3,
+        radius: f32,
+        material: Arc<dyn Material + Sync + Send>,
+    ) -> Self {
+        let normal = normal.unit_vector();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        Self {
+            center,
+            normal,
+            radius,
+            tangent,
+            bitangent,
+            material,
+        }
+    }
+}
+
+impl Hitable for Disk {
+    /// u is the angle around the center and v the distance from
+    /// it, both scaled to [0,1]
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        match hit_plane(r, &self.center, &self.normal, t_min, t_max) {
+            Some(t) => {
+                let p = r.point_at_parameter(t);
+                let d = p - self.center;
+                let dist = d.length();
+                if dist > self.radius {
+                    return false;
+                }
+                let phi = d.dot(self.bitangent).atan2(d.dot(self.tangent)) + std::f32::consts::PI;
+                *rec = HitRecord::new(t, p, self.normal, self.material.clone());
+                rec.set_face_normal(r, &self.normal);
+                rec.set_uv(phi / (2. * std::f32::consts::PI), dist / self.radius);
+                rec.set_tangents(
+                    self.normal.cross(d) * (2. * std::f32::consts::PI),
+                    d * (self.radius / dist.max(f32::EPSILON)),
+                );
+                true
+            }
+            None => false,
+        }
+    }
+    /// On every axis the disk extends by radius * sin(angle between the axis and the normal)
+    fn bounding_box(&self) -> Option<Aabb> {
+        let n = self.normal;
+        let e = Vec3::new(
+            (1. - n.x * n.x).max(0.).sqrt(),
+            (1. - n.y * n.y).max(0.).sqrt(),
+            (1. - n.z * n.z).max(0.).sqrt(),
+        ) * self.radius;
+        Some(Aabb::new(self.center - e, self.center + e).grow(1e-4))
+    }
+}
+
+/// An axis aligned box made of six quads, all facing outwards
+pub struct Cuboid {
+    sides: HitableList,
+}
+
+impl Cuboid {
+    /// 'a' and 'b' are two opposite corners of the box
+    pub fn new(a: Vec3, b: Vec3, material: Arc<dyn Material + Sync + Send>) -> Self {
+        let min = Vec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+        let max = Vec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+        let dx = Vec3::new(max.x - min.x, 0., 0.);
+        let dy = Vec3::new(0., max.y - min.y, 0.);
+        let dz = Vec3::new(0., 0., max.z - min.z);
+        Self {
+            sides: HitableList {
+                list: vec![
+                    // front
+                    Box::new(Quad::new(Vec3::new(min.x, min.y, max.z), dx, dy, material.clone())),
+                    // right
+                    Box::new(Quad::new(Vec3::new(max.x, min.y, max.z), -dz, dy, material.clone())),
+                    // back
+                    Box::new(Quad::new(Vec3::new(max.x, min.y, min.z), -dx, dy, material.clone())),
+                    // left
+                    Box::new(Quad::new(Vec3::new(min.x, min.y, min.z), dz, dy, material.clone())),
+                    // top
+                    Box::new(Quad::new(Vec3::new(min.x, max.y, max.z), dx, -dz, material.clone())),
+                    // bottom
+                    Box::new(Quad::new(Vec3::new(min.x, min.y, min.z), dx, dz, material)),
+                ],
+                lights: LightTree::default(),
+                sky: None,
+            },
+        }
+    }
+}
+
+impl Hitable for Cuboid {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        self.sides.hit_list(r, t_min, t_max, rec)
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.sides.bounding_box()
+    }
+}
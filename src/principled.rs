@@ -0,0 +1,389 @@
+use crate::hitables::*;
+use crate::materials::*;
+use crate::microfacet::*;
+use crate::mylib::*;
+use crate::textures::*;
+use crate::vecmath::Vec3;
+use rand::Rng;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// The parameters of Principled, to give any of them a texture
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrincipledParameter {
+    BaseColor,
+    Metallic,
+    Roughness,
+    Specular,
+    SpecularTint,
+    Sheen,
+    SheenTint,
+    Clearcoat,
+    ClearcoatGloss,
+    Transmission,
+    Anisotropic,
+}
+
+// The parameters read from the textures at a hit
+struct Parameters {
+    base_color: Vec3,
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    specular_tint: f32,
+    sheen: f32,
+    sheen_tint: f32,
+    clearcoat: f32,
+    clearcoat_gloss: f32,
+    transmission: f32,
+    anisotropic: f32,
+}
+
+impl Parameters {
+    /// Index of refraction giving the reflectance of 'specular'
+    fn eta(&self) -> f32 {
+        let f0 = (0.08 * self.specular).clamp(1e-4, 0.9).sqrt();
+        (1. + f0) / (1. - f0)
+    }
+    fn specular_distribution(&self) -> Ggx {
+        // Alpha is divided and multiplied by the aspect, the roughness
+        // by its square root
+        let aspect = (1. - 0.9 * self.anisotropic).sqrt();
+        Ggx::new(self.roughness / aspect.sqrt(), self.roughness * aspect.sqrt())
+    }
+}
+
+/// A material which can look like most of the others, after the one
+/// Disney uses for its films (Burley 2012 and 2015). Every parameter
+/// but the base color goes from 0 to 1 and can come from a texture.
+/// It is made of a diffuse lobe with sheen, a GGX specular lobe tinted
+/// by the base color as it becomes a metal, a rough glass lobe for
+/// transmission and a clearcoat on top. The clearcoat uses GGX as well
+/// instead of the GTR1 distribution of the paper
+pub struct Principled {
+    base_color: Arc<dyn Texture + Sync + Send>,
+    metallic: Arc<dyn Texture + Sync + Send>,
+    roughness: Arc<dyn Texture + Sync + Send>,
+    specular: Arc<dyn Texture + Sync + Send>,
+    specular_tint: Arc<dyn Texture + Sync + Send>,
+    sheen: Arc<dyn Texture + Sync + Send>,
+    sheen_tint: Arc<dyn Texture + Sync + Send>,
+    clearcoat: Arc<dyn Texture + Sync + Send>,
+    clearcoat_gloss: Arc<dyn Texture + Sync + Send>,
+    transmission: Arc<dyn Texture + Sync + Send>,
+    anisotropic: Arc<dyn Texture + Sync + Send>,
+}
+
+fn constant(value: f32) -> Arc<dyn Texture + Sync + Send> {
+    Arc::new(SolidColor::scalar(value))
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    a * (1. - t) + b * t
+}
+
+fn luminance(c: Vec3) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+fn schlick_weight(cosine: f32) -> f32 {
+    (1. - cosine.clamp(0., 1.)).powi(5)
+}
+
+fn schlick_color(f0: Vec3, cosine: f32) -> Vec3 {
+    lerp(f0, Vec3::new(1., 1., 1.), schlick_weight(cosine))
+}
+
+impl Principled {
+    /// A plastic looking dielectric of the given color
+    pub fn new(base_color: Vec3) -> Self {
+        Self {
+            base_color: Arc::new(SolidColor::new(base_color)),
+            metallic: constant(0.),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.),
+            sheen: constant(0.),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.),
+            clearcoat_gloss: constant(1.),
+            transmission: constant(0.),
+            anisotropic: constant(0.),
+        }
+    }
+    /// Reads the parameter from a texture instead, scalar ones take
+    /// the first channel
+    pub fn with_texture(
+        mut self,
+        parameter: PrincipledParameter,
+        texture: Arc<dyn Texture + Sync + Send>,
+    ) -> Self {
+        use PrincipledParameter::*;
+        match parameter {
+            BaseColor => self.base_color = texture,
+            Metallic => self.metallic = texture,
+            Roughness => self.roughness = texture,
+            Specular => self.specular = texture,
+            SpecularTint => self.specular_tint = texture,
+            Sheen => self.sheen = texture,
+            SheenTint => self.sheen_tint = texture,
+            Clearcoat => self.clearcoat = texture,
+            ClearcoatGloss => self.clearcoat_gloss = texture,
+            Transmission => self.transmission = texture,
+            Anisotropic => self.anisotropic = texture,
+        }
+        self
+    }
+    /// 0 is a dielectric, 1 a metal colored by the base color
+    pub fn with_metallic(self, metallic: f32) -> Self {
+        self.with_texture(PrincipledParameter::Metallic, constant(metallic))
+    }
+    pub fn with_roughness(self, roughness: f32) -> Self {
+        self.with_texture(PrincipledParameter::Roughness, constant(roughness))
+    }
+    /// How much a dielectric reflects, the default 0.5 is an index of
+    /// refraction of 1.5. It also gives the index used by transmission
+    pub fn with_specular(self, specular: f32) -> Self {
+        self.with_texture(PrincipledParameter::Specular, constant(specular))
+    }
+    /// Colors the reflection of a dielectric with the base color
+    pub fn with_specular_tint(self, specular_tint: f32) -> Self {
+        self.with_texture(PrincipledParameter::SpecularTint, constant(specular_tint))
+    }
+    /// Extra reflection at grazing angles, for cloth
+    pub fn with_sheen(self, sheen: f32) -> Self {
+        self.with_texture(PrincipledParameter::Sheen, constant(sheen))
+    }
+    pub fn with_sheen_tint(self, sheen_tint: f32) -> Self {
+        self.with_texture(PrincipledParameter::SheenTint, constant(sheen_tint))
+    }
+    /// A second, clear and white, specular layer like car paint
+    pub fn with_clearcoat(self, clearcoat: f32) -> Self {
+        self.with_texture(PrincipledParameter::Clearcoat, constant(clearcoat))
+    }
+    /// 0 is a satin clearcoat, 1 a glossy one
+    pub fn with_clearcoat_gloss(self, clearcoat_gloss: f32) -> Self {
+        self.with_texture(PrincipledParameter::ClearcoatGloss, constant(clearcoat_gloss))
+    }
+    /// Turns the dielectric into glass tinted by the base color. The
+    /// object must be closed like for Dieletric
+    pub fn with_transmission(self, transmission: f32) -> Self {
+        self.with_texture(PrincipledParameter::Transmission, constant(transmission))
+    }
+    /// Stretches the highlights along the first tangent of the surface
+    pub fn with_anisotropic(self, anisotropic: f32) -> Self {
+        self.with_texture(PrincipledParameter::Anisotropic, constant(anisotropic))
+    }
+
+    fn parameters(&self, rec: &HitRecord) -> Parameters {
+        let scalar = |t: &Arc<dyn Texture + Sync + Send>| rec.lookup(&**t).x.clamp(0., 1.);
+        Parameters {
+            base_color: rec.lookup(&*self.base_color),
+            metallic: scalar(&self.metallic),
+            roughness: scalar(&self.roughness),
+            specular: scalar(&self.specular),
+            specular_tint: scalar(&self.specular_tint),
+            sheen: scalar(&self.sheen),
+            sheen_tint: scalar(&self.sheen_tint),
+            clearcoat: scalar(&self.clearcoat),
+            clearcoat_gloss: scalar(&self.clearcoat_gloss),
+            transmission: scalar(&self.transmission),
+            anisotropic: scalar(&self.anisotropic),
+        }
+    }
+    // What eval() and pdf() give, from a single look at the parameters
+    fn evaluate(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> (Vec3, f32) {
+        let frame = Frame::from_tangent(rec.get_normal(), rec.get_tangents().0);
+        let wo = frame.to_local(-r_in.get_direction().unit_vector());
+        let wi = frame.to_local(wi);
+        if wo.z <= 0. {
+            return (Vec3::new(0., 0., 0.), 0.);
+        }
+        let (value, pdf) = if rec.get_front_face() {
+            match Lobes::new(&self.parameters(rec), wo) {
+                Some(lobes) => lobes.evaluate(wo, wi),
+                None => return (Vec3::new(0., 0., 0.), 0.),
+            }
+        } else {
+            let c = self.parameters(rec);
+            let (value, pdf) = c.specular_distribution().dielectric(wo, wi, 1. / c.eta());
+            (Vec3::new(value, value, value), pdf)
+        };
+        (value * wi.z.abs(), pdf)
+    }
+}
+
+// Everything needed to evaluate and sample the lobes, once the
+// parameters are known
+struct Lobes {
+    base_color: Vec3,
+    roughness: f32,
+    sheen_color: Vec3,
+    specular_f0: Vec3,
+    eta: f32,
+    specular: Ggx,
+    clearcoat: Ggx,
+    // How much each lobe counts
+    diffuse_weight: f32,
+    specular_weight: f32,
+    transmission_weight: f32,
+    clearcoat_weight: f32,
+    // The probability of sampling each lobe, adding up to 1
+    probabilities: [f32; 4],
+}
+
+impl Lobes {
+    fn new(c: &Parameters, wo: Vec3) -> Option<Self> {
+        let white = Vec3::new(1., 1., 1.);
+        let lum = luminance(c.base_color);
+        let tint = if lum > 0. { c.base_color / lum } else { white };
+        let dielectric_f0 = lerp(white, tint, c.specular_tint) * (0.08 * c.specular);
+        let specular_f0 = lerp(dielectric_f0, c.base_color, c.metallic);
+        let gloss_alpha = 0.1 * (1. - c.clearcoat_gloss) + 0.001 * c.clearcoat_gloss;
+
+        let diffuse_weight = (1. - c.metallic) * (1. - c.transmission);
+        let transmission_weight = (1. - c.metallic) * c.transmission;
+        let specular_weight = 1. - transmission_weight;
+        let clearcoat_weight = 0.25 * c.clearcoat;
+        // About how much light each lobe sends back
+        let estimates = [
+            diffuse_weight,
+            specular_weight * luminance(schlick_color(specular_f0, wo.z)),
+            transmission_weight,
+            clearcoat_weight * schlick_color(Vec3::new(0.04, 0.04, 0.04), wo.z).x,
+        ];
+        let total: f32 = estimates.iter().sum();
+        if total <= 0. {
+            return None;
+        }
+        let mut probabilities = [0.; 4];
+        for (p, e) in probabilities.iter_mut().zip(estimates.iter()) {
+            *p = e / total;
+        }
+        Some(Self {
+            base_color: c.base_color,
+            roughness: c.roughness,
+            sheen_color: lerp(white, tint, c.sheen_tint) * c.sheen,
+            specular_f0,
+            eta: c.eta(),
+            specular: c.specular_distribution(),
+            clearcoat: Ggx::new(gloss_alpha.sqrt(), gloss_alpha.sqrt()),
+            diffuse_weight,
+            specular_weight,
+            transmission_weight,
+            clearcoat_weight,
+            probabilities,
+        })
+    }
+
+    /// Value of the whole BSDF and probability density of sampling 'wi'
+    /// through sample(), all in the local space of the normal
+    fn evaluate(&self, wo: Vec3, wi: Vec3) -> (Vec3, f32) {
+        let mut value = Vec3::new(0., 0., 0.);
+        let mut pdf = 0.;
+        if wi.z > 0. {
+            let h = (wo + wi).unit_vector();
+            let cos_d = wi.dot(h);
+            // Burley's diffuse, with retro-reflection on rough surfaces
+            let fd90 = 0.5 + 2. * self.roughness * cos_d * cos_d;
+            let fd = (1. + (fd90 - 1.) * schlick_weight(wi.z))
+                * (1. + (fd90 - 1.) * schlick_weight(wo.z));
+            let sheen = self.sheen_color * schlick_weight(cos_d);
+            value += (self.base_color * (fd / PI) + sheen) * self.diffuse_weight;
+            pdf += self.probabilities[0] * wi.z / PI;
+
+            let reflection = |ggx: &Ggx| {
+                ggx.d(h) * ggx.g2(wo, wi) / (4. * wo.z * wi.z)
+            };
+            let specular = schlick_color(self.specular_f0, cos_d) * reflection(&self.specular);
+            value += specular * self.specular_weight;
+            pdf += self.probabilities[1] * self.specular.visible_d(wo, h) / (4. * wo.dot(h));
+
+            let clearcoat = schlick_color(Vec3::new(0.04, 0.04, 0.04), cos_d).x
+                * reflection(&self.clearcoat);
+            value += Vec3::new(1., 1., 1.) * (clearcoat * self.clearcoat_weight);
+            pdf += self.probabilities[3] * self.clearcoat.visible_d(wo, h) / (4. * wo.dot(h));
+        }
+        let (glass, glass_pdf) = self.specular.dielectric(wo, wi, self.eta);
+        // The light going through is tinted, what is reflected is not
+        let tint = if wi.z < 0. {
+            self.base_color
+        } else {
+            Vec3::new(1., 1., 1.)
+        };
+        value += tint * (glass * self.transmission_weight);
+        pdf += self.probabilities[2] * glass_pdf;
+        (value, pdf)
+    }
+
+    fn sample(&self, wo: Vec3) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let (u1, u2) = (rng.gen::<f32>(), rng.gen::<f32>());
+        let reflect = |m: Vec3| m * (2. * wo.dot(m)) - wo;
+        let mut choice = rng.gen::<f32>();
+        let mut lobe = 0;
+        while lobe < 3 && choice >= self.probabilities[lobe] {
+            choice -= self.probabilities[lobe];
+            lobe += 1;
+        }
+        match lobe {
+            // Cosine weighted
+            0 => {
+                let r = u1.sqrt();
+                let phi = 2. * PI * u2;
+                Vec3::new(r * phi.cos(), r * phi.sin(), (1. - u1).sqrt())
+            }
+            1 => reflect(self.specular.sample_visible_normal(wo, u1, u2)),
+            2 => self.specular.sample_dielectric(wo, self.eta, u1, u2, rng.gen()),
+            _ => reflect(self.clearcoat.sample_visible_normal(wo, u1, u2)),
+        }
+    }
+}
+
+impl Material for Principled {
+    /// One of the lobes is picked to sample the direction, and the
+    /// attenuation is the whole BSDF over the combined density of all
+    /// the lobes. Inside a transmissive object only the glass counts
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let frame = Frame::from_tangent(rec.get_normal(), rec.get_tangents().0);
+        let wo = frame.to_local(-r_in.get_direction().unit_vector());
+        if wo.z <= 0. {
+            return false;
+        }
+        let (wi, value, pdf) = if rec.get_front_face() {
+            let lobes = match Lobes::new(&self.parameters(rec), wo) {
+                Some(lobes) => lobes,
+                None => return false,
+            };
+            let wi = lobes.sample(wo);
+            let (value, pdf) = lobes.evaluate(wo, wi);
+            (wi, value, pdf)
+        } else {
+            let c = self.parameters(rec);
+            let eta = 1. / c.eta();
+            let ggx = c.specular_distribution();
+            let mut rng = rand::thread_rng();
+            let wi = ggx.sample_dielectric(wo, eta, rng.gen(), rng.gen(), rng.gen());
+            let (value, pdf) = ggx.dielectric(wo, wi, eta);
+            (wi, Vec3::new(value, value, value), pdf)
+        };
+        if pdf <= 0. {
+            return false;
+        }
+        *attenuation = value * (wi.z.abs() / pdf);
+        *scattered = Ray::new(rec.get_p(), frame.to_world(wi));
+        true
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> Vec3 {
+        self.evaluate(r_in, rec, wi).0
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, wi: Vec3) -> f32 {
+        self.evaluate(r_in, rec, wi).1
+    }
+}
@@ -0,0 +1,670 @@
+use crate::hitables::*;
+use crate::materials::*;
+use crate::mylib::*;
+use crate::vecmath::Vec3;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+// All the shapes in this file are built around the y axis and have
+// their base on the origin, wrap them in a Transform to place them.
+// 'phi' is the angle around the y axis, starting from +x and going
+// towards +z, a shape is only swept from 0 up to its 'phi_max'
+
+fn phi_of(p: &Vec3) -> f32 {
+    let phi = p.z.atan2(p.x);
+    if phi < 0. {
+        phi + 2. * PI
+    } else {
+        phi
+    }
+}
+
+/// Real roots of a*x^2 + b*x + c in increasing order, a can be 0
+pub fn solve_quadratic(a: f32, b: f32, c: f32) -> Vec<f32> {
+    if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return vec![];
+        }
+        return vec![-c / b];
+    }
+    let delta = b * b - 4. * a * c;
+    if delta < 0. {
+        return vec![];
+    }
+    // Avoid the cancellation between -b and the square root
+    let q = -0.5 * (b + b.signum() * delta.sqrt());
+    let (x0, x1) = if q == 0. { (0., 0.) } else { (q / a, c / q) };
+    if x0 < x1 {
+        vec![x0, x1]
+    } else {
+        vec![x1, x0]
+    }
+}
+
+// The biggest real root of x^3 + a*x^2 + b*x + c
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    // With x = y - a/3 the cubic becomes y^3 + p*y + q
+    let p = b - a * a / 3.;
+    let q = 2. * a * a * a / 27. - a * b / 3. + c;
+    let delta = q * q / 4. + p * p * p / 27.;
+    let y = if delta > 0. {
+        (-q / 2. + delta.sqrt()).cbrt() + (-q / 2. - delta.sqrt()).cbrt()
+    } else if p == 0. {
+        0.
+    } else {
+        // Three real roots, the k = 0 one is the biggest
+        let m = 2. * (-p / 3.).sqrt();
+        let theta = (3. * q / (p * m)).clamp(-1., 1.).acos() / 3.;
+        m * theta.cos()
+    };
+    y - a / 3.
+}
+
+/// Real roots of c4*x^4 + c3*x^3 + c2*x^2 + c1*x + c0 in increasing
+/// order, found with Ferrari's method and then refined with Newton
+pub fn solve_quartic(c4: f64, c3: f64, c2: f64, c1: f64, c0: f64) -> Vec<f64> {
+    let a = c3 / c4;
+    let b = c2 / c4;
+    let c = c1 / c4;
+    let d = c0 / c4;
+    // With x = y - a/4 the quartic becomes y^4 + p*y^2 + q*y + r
+    let p = b - 3. * a * a / 8.;
+    let q = c - a * b / 2. + a * a * a / 8.;
+    let r = d - a * c / 4. + a * a * b / 16. - 3. * a * a * a * a / 256.;
+
+    let mut ys = Vec::with_capacity(4);
+    if q.abs() < 1e-12 {
+        // Quadratic in y^2
+        let delta = p * p - 4. * r;
+        if delta >= 0. {
+            for z in [(-p - delta.sqrt()) / 2., (-p + delta.sqrt()) / 2.] {
+                if z >= 0. {
+                    ys.push(z.sqrt());
+                    ys.push(-z.sqrt());
+                }
+            }
+        }
+    } else {
+        // Split the quartic in the product of two quadratics
+        let m = largest_cubic_root(p, p * p / 4. - r, -q * q / 8.);
+        if m <= 0. {
+            return vec![];
+        }
+        let s = (2. * m).sqrt();
+        for (sign, k) in [(-1., 1.), (1., -1.)] {
+            // y^2 + sign*s*y + (p/2 + m + k*q/(2s)) = 0
+            let bb = sign * s;
+            let cc = p / 2. + m + k * q / (2. * s);
+            let delta = bb * bb - 4. * cc;
+            if delta >= 0. {
+                ys.push((-bb - delta.sqrt()) / 2.);
+                ys.push((-bb + delta.sqrt()) / 2.);
+            }
+        }
+    }
+
+    let mut roots: Vec<f64> = ys
+        .into_iter()
+        .map(|y| {
+            let mut x = y - a / 4.;
+            for _ in 0..2 {
+                let f = (((c4 * x + c3) * x + c2) * x + c1) * x + c0;
+                let df = ((4. * c4 * x + 3. * c3) * x + 2. * c2) * x + c1;
+                if df != 0. {
+                    x -= f / df;
+                }
+            }
+            x
+        })
+        // Degenerate coefficients can give NaN or infinite roots
+        .filter(|x| x.is_finite())
+        .collect();
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+// The closest valid intersection found so far while testing the
+// different surfaces of a shape
+struct Candidate {
+    t: f32,
+    outward_normal: Vec3,
+    u: f32,
+    v: f32,
+}
+
+impl Candidate {
+    fn write(self, r: &Ray, material: &Arc<dyn Material + Sync + Send>, rec: &mut HitRecord) {
+        *rec = HitRecord::new(
+            self.t,
+            r.point_at_parameter(self.t),
+            self.outward_normal,
+            material.clone(),
+        );
+        rec.set_face_normal(r, &self.outward_normal);
+        rec.set_uv(self.u, self.v);
+    }
+}
+
+// Keeps 'candidate' if it is in front of what was found before
+fn closest(best: &mut Option<Candidate>, candidate: Candidate) {
+    if best.as_ref().is_none_or(|b| candidate.t < b.t) {
+        *best = Some(candidate);
+    }
+}
+
+// A flat cap on the plane y = 'y' of the given radius, only the part
+// inside the sweep is kept
+fn hit_cap(
+    r: &Ray,
+    y: f32,
+    radius: f32,
+    phi_max: f32,
+    facing_up: bool,
+    t_min: f32,
+    t_max: f32,
+) -> Option<Candidate> {
+    let dy = r.get_direction().y;
+    if dy.abs() < 1e-8 {
+        return None;
+    }
+    let t = (y - r.get_origin().y) / dy;
+    if t <= t_min || t >= t_max {
+        return None;
+    }
+    let p = r.point_at_parameter(t);
+    let dist = (p.x * p.x + p.z * p.z).sqrt();
+    let phi = phi_of(&p);
+    if dist > radius || phi > phi_max {
+        return None;
+    }
+    Some(Candidate {
+        t,
+        outward_normal: Vec3::new(0., if facing_up { 1. } else { -1. }, 0.),
+        u: phi / phi_max,
+        v: if radius > 0. { dist / radius } else { 0. },
+    })
+}
+
+/// A cylinder of the given radius going from y = 0 to y = 'height'
+pub struct Cylinder {
+    radius: f32,
+    y_min: f32,
+    y_max: f32,
+    phi_max: f32,
+    capped: bool,
+    material: Arc<dyn Material + Sync + Send>,
+}
+
+impl Cylinder {
+    pub fn new(radius: f32, height: f32, material: Arc<dyn Material + Sync + Send>) -> Self {
+        Self {
+            radius,
+            y_min: 0.,
+            y_max: height,
+            phi_max: 2. * PI,
+            capped: false,
+            material,
+        }
+    }
+    /// Only keep the part of the surface from 0 to 'degrees' around the axis
+    pub fn with_sweep(mut self, degrees: f32) -> Self {
+        self.phi_max = degrees.clamp(0., 360.) * PI / 180.;
+        self
+    }
+    /// Cut the cylinder between the two heights
+    pub fn with_height_limits(mut self, y_min: f32, y_max: f32) -> Self {
+        self.y_min = y_min.max(self.y_min);
+        self.y_max = y_max.min(self.y_max);
+        self
+    }
+    /// Close the two ends with disks
+    pub fn capped(mut self) -> Self {
+        self.capped = true;
+        self
+    }
+}
+
+impl Hitable for Cylinder {
+    /// u goes around the axis and v along it
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let o = r.get_origin();
+        let d = r.get_direction();
+        let mut best = None;
+        for t in solve_quadratic(
+            d.x * d.x + d.z * d.z,
+            2. * (o.x * d.x + o.z * d.z),
+            o.x * o.x + o.z * o.z - self.radius * self.radius,
+        ) {
+            if t <= t_min || t >= t_max {
+                continue;
+            }
+            let p = r.point_at_parameter(t);
+            let phi = phi_of(&p);
+            if p.y < self.y_min || p.y > self.y_max || phi > self.phi_max {
+                continue;
+            }
+            closest(
+                &mut best,
+                Candidate {
+                    t,
+                    outward_normal: Vec3::new(p.x, 0., p.z) / self.radius,
+                    u: phi / self.phi_max,
+                    v: (p.y - self.y_min) / (self.y_max - self.y_min),
+                },
+            );
+            break;
+        }
+        if self.capped {
+            for (y, up) in [(self.y_min, false), (self.y_max, true)] {
+                if let Some(c) = hit_cap(r, y, self.radius, self.phi_max, up, t_min, t_max) {
+                    closest(&mut best, c);
+                }
+            }
+        }
+        match best {
+            Some(c) => {
+                c.write(r, &self.material, rec);
+                true
+            }
+            None => false,
+        }
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(
+            Vec3::new(-self.radius, self.y_min, -self.radius),
+            Vec3::new(self.radius, self.y_max, self.radius),
+        ))
+    }
+}
+
+/// A cone with a base of the given radius on y = 0 and the tip in
+/// y = 'height'
+pub struct Cone {
+    radius: f32,
+    height: f32,
+    y_min: f32,
+    y_max: f32,
+    phi_max: f32,
+    capped: bool,
+    material: Arc<dyn Material + Sync + Send>,
+}
+
+impl Cone {
+    pub fn new(radius: f32, height: f32, material: Arc<dyn Material + Sync + Send>) -> Self {
+        Self {
+            radius,
+            height,
+            y_min: 0.,
+            y_max: height,
+            phi_max: 2. * PI,
+            capped: false,
+            material,
+        }
+    }
+    /// Only keep the part of the surface from 0 to 'degrees' around the axis
+    pub fn with_sweep(mut self, degrees: f32) -> Self {
+        self.phi_max = degrees.clamp(0., 360.) * PI / 180.;
+        self
+    }
+    /// Cut the cone between the two heights, cutting the tip off
+    /// leaves a frustum
+    pub fn with_height_limits(mut self, y_min: f32, y_max: f32) -> Self {
+        self.y_min = y_min.max(self.y_min);
+        self.y_max = y_max.min(self.y_max);
+        self
+    }
+    /// Close the base, and the top too when the tip was cut
+    pub fn capped(mut self) -> Self {
+        self.capped = true;
+        self
+    }
+    fn radius_at(&self, y: f32) -> f32 {
+        self.radius * (self.height - y) / self.height
+    }
+}
+
+impl Hitable for Cone {
+    /// u goes around the axis and v along it
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let o = r.get_origin();
+        let d = r.get_direction();
+        // x^2 + z^2 = k^2 * (height - y)^2
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let h = self.height - o.y;
+        let mut best = None;
+        for t in solve_quadratic(
+            d.x * d.x + d.z * d.z - k2 * d.y * d.y,
+            2. * (o.x * d.x + o.z * d.z + k2 * h * d.y),
+            o.x * o.x + o.z * o.z - k2 * h * h,
+        ) {
+            if t <= t_min || t >= t_max {
+                continue;
+            }
+            let p = r.point_at_parameter(t);
+            let phi = phi_of(&p);
+            // The equation also describes the upside down cone above the tip
+            if p.y < self.y_min || p.y > self.y_max || phi > self.phi_max {
+                continue;
+            }
+            closest(
+                &mut best,
+                Candidate {
+                    t,
+                    outward_normal: Vec3::new(p.x, k2 * (self.height - p.y), p.z).unit_vector(),
+                    u: phi / self.phi_max,
+                    v: (p.y - self.y_min) / (self.y_max - self.y_min),
+                },
+            );
+            break;
+        }
+        if self.capped {
+            for (y, up) in [(self.y_min, false), (self.y_max, true)] {
+                let radius = self.radius_at(y);
+                if radius <= 0. {
+                    continue;
+                }
+                if let Some(c) = hit_cap(r, y, radius, self.phi_max, up, t_min, t_max) {
+                    closest(&mut best, c);
+                }
+            }
+        }
+        match best {
+            Some(c) => {
+                c.write(r, &self.material, rec);
+                true
+            }
+            None => false,
+        }
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius_at(self.y_min);
+        Some(Aabb::new(
+            Vec3::new(-r, self.y_min, -r),
+            Vec3::new(r, self.y_max, r),
+        ))
+    }
+}
+
+/// A paraboloid with the vertex in the origin, opening upwards and
+/// reaching the given radius at y = 'height'
+pub struct Paraboloid {
+    radius: f32,
+    height: f32,
+    y_min: f32,
+    y_max: f32,
+    phi_max: f32,
+    capped: bool,
+    material: Arc<dyn Material + Sync + Send>,
+}
+
+impl Paraboloid {
+    pub fn new(radius: f32, height: f32, material: Arc<dyn Material + Sync + Send>) -> Self {
+        Self {
+            radius,
+            height,
+            y_min: 0.,
+            y_max: height,
+            phi_max: 2. * PI,
+            capped: false,
+            material,
+        }
+    }
+    /// Only keep the part of the surface from 0 to 'degrees' around the axis
+    pub fn with_sweep(mut self, degrees: f32) -> Self {
+        self.phi_max = degrees.clamp(0., 360.) * PI / 180.;
+        self
+    }
+    /// Cut the paraboloid between the two heights
+    pub fn with_height_limits(mut self, y_min: f32, y_max: f32) -> Self {
+        self.y_min = y_min.max(self.y_min);
+        self.y_max = y_max.min(self.y_max);
+        self
+    }
+    /// Close the top, and the bottom too when the vertex was cut
+    pub fn capped(mut self) -> Self {
+        self.capped = true;
+        self
+    }
+}
+
+impl Hitable for Paraboloid {
+    /// u goes around the axis and v along it
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let o = r.get_origin();
+        let d = r.get_direction();
+        // x^2 + z^2 = k * y
+        let k = self.radius * self.radius / self.height;
+        let mut best = None;
+        for t in solve_quadratic(
+            d.x * d.x + d.z * d.z,
+            2. * (o.x * d.x + o.z * d.z) - k * d.y,
+            o.x * o.x + o.z * o.z - k * o.y,
+        ) {
+            if t <= t_min || t >= t_max {
+                continue;
+            }
+            let p = r.point_at_parameter(t);
+            let phi = phi_of(&p);
+            if p.y < self.y_min || p.y > self.y_max || phi > self.phi_max {
+                continue;
+            }
+            closest(
+                &mut best,
+                Candidate {
+                    t,
+                    outward_normal: Vec3::new(2. * p.x, -k, 2. * p.z).unit_vector(),
+                    u: phi / self.phi_max,
+                    v: (p.y - self.y_min) / (self.y_max - self.y_min),
+                },
+            );
+            break;
+        }
+        if self.capped {
+            for (y, up) in [(self.y_min, false), (self.y_max, true)] {
+                let radius = (k * y).max(0.).sqrt();
+                if radius <= 0. {
+                    continue;
+                }
+                if let Some(c) = hit_cap(r, y, radius, self.phi_max, up, t_min, t_max) {
+                    closest(&mut best, c);
+                }
+            }
+        }
+        match best {
+            Some(c) => {
+                c.write(r, &self.material, rec);
+                true
+            }
+            None => false,
+        }
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius * (self.y_max / self.height).max(0.).sqrt();
+        Some(Aabb::new(
+            Vec3::new(-r, self.y_min, -r),
+            Vec3::new(r, self.y_max, r),
+        ))
+    }
+}
+
+/// A donut lying on the xz plane, 'major_radius' is the distance from
+/// the center to the middle of the tube and 'minor_radius' is the
+/// radius of the tube
+pub struct Torus {
+    major_radius: f32,
+    minor_radius: f32,
+    y_min: f32,
+    y_max: f32,
+    phi_max: f32,
+    material: Arc<dyn Material + Sync + Send>,
+}
+
+impl Torus {
+    pub fn new(
+        major_radius: f32,
+        minor_radius: f32,
+        material: Arc<dyn Material + Sync + Send>,
+    ) -> Self {
+        Self {
+            major_radius,
+            minor_radius,
+            y_min: -minor_radius,
+            y_max: minor_radius,
+            phi_max: 2. * PI,
+            material,
+        }
+    }
+    /// Only keep the part of the surface from 0 to 'degrees' around the axis
+    pub fn with_sweep(mut self, degrees: f32) -> Self {
+        self.phi_max = degrees.clamp(0., 360.) * PI / 180.;
+        self
+    }
+    /// Cut the torus between the two heights
+    pub fn with_height_limits(mut self, y_min: f32, y_max: f32) -> Self {
+        self.y_min = y_min.max(self.y_min);
+        self.y_max = y_max.min(self.y_max);
+        self
+    }
+}
+
+impl Hitable for Torus {
+    /// u goes around the y axis and v around the tube, starting from
+    /// its outer side. The quartic is solved in f64, in f32 the roots
+    /// are too imprecise for rays coming from far away
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let o = r.get_origin();
+        let d = r.get_direction();
+        let (ox, oy, oz) = (o.x as f64, o.y as f64, o.z as f64);
+        let (dx, dy, dz) = (d.x as f64, d.y as f64, d.z as f64);
+        let big_r2 = (self.major_radius as f64).powi(2);
+        let small_r2 = (self.minor_radius as f64).powi(2);
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 * R^2 * (x^2 + z^2)
+        let dd = dx * dx + dy * dy + dz * dz;
+        let e = ox * ox + oy * oy + oz * oz - big_r2 - small_r2;
+        let f = ox * dx + oy * dy + oz * dz;
+        let four_r2 = 4. * big_r2;
+        let roots = solve_quartic(
+            dd * dd,
+            4. * dd * f,
+            2. * dd * e + 4. * f * f + four_r2 * dy * dy,
+            4. * f * e + 2. * four_r2 * oy * dy,
+            e * e - four_r2 * (small_r2 - oy * oy),
+        );
+        for t in roots {
+            let t = t as f32;
+            if t <= t_min || t >= t_max {
+                continue;
+            }
+            let p = r.point_at_parameter(t);
+            let phi = phi_of(&p);
+            if p.y < self.y_min || p.y > self.y_max || phi > self.phi_max {
+                continue;
+            }
+            let s = p.squared_len() - self.major_radius * self.major_radius
+                - self.minor_radius * self.minor_radius;
+            let outward_normal = Vec3::new(
+                p.x * s,
+                p.y * (s + 2. * self.major_radius * self.major_radius),
+                p.z * s,
+            )
+            .unit_vector();
+            let ring = (p.x * p.x + p.z * p.z).sqrt() - self.major_radius;
+            let mut theta = p.y.atan2(ring);
+            if theta < 0. {
+                theta += 2. * PI;
+            }
+            Candidate {
+                t,
+                outward_normal,
+                u: phi / self.phi_max,
+                v: theta / (2. * PI),
+            }
+            .write(r, &self.material, rec);
+            return true;
+        }
+        false
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.major_radius + self.minor_radius;
+        Some(Aabb::new(
+            Vec3::new(-r, self.y_min, -r),
+            Vec3::new(r, self.y_max, r),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(roots: &[f64], expected: &[f64]) {
+        assert_eq!(roots.len(), expected.len(), "{:?}", roots);
+        for (x, e) in roots.iter().zip(expected) {
+            assert!((x - e).abs() < 1e-6, "{:?} != {:?}", roots, expected);
+        }
+    }
+
+    #[test]
+    fn quartic_with_four_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 5), scaled so c4 is not 1
+        let roots = solve_quartic(2., -22., 82., -122., 60.);
+        assert_roots(&roots, &[1., 2., 3., 5.]);
+    }
+
+    #[test]
+    fn quartic_with_a_double_root() {
+        // (x - 1)^2 (x - 3)(x + 2)
+        let roots = solve_quartic(1., -3., -3., 11., -6.);
+        assert!(roots.len() >= 3, "{:?}", roots);
+        assert!((roots[0] + 2.).abs() < 1e-6);
+        assert!((roots[roots.len() - 1] - 3.).abs() < 1e-6);
+        // Newton only converges slowly on a double root
+        for x in &roots[1..roots.len() - 1] {
+            assert!((x - 1.).abs() < 1e-3, "{:?}", roots);
+        }
+    }
+
+    #[test]
+    fn quartic_without_real_roots() {
+        // (x^2 + 1)(x^2 + 2x + 5)
+        assert!(solve_quartic(1., 2., 6., 2., 5.).is_empty());
+        // (x^2 + 1)(x^2 + 4), which is biquadratic
+        assert!(solve_quartic(1., 0., 5., 0., 4.).is_empty());
+    }
+
+    #[test]
+    fn biquadratic_quartic() {
+        // (x^2 - 1)(x^2 - 4), the depressed quartic has q = 0
+        assert_roots(&solve_quartic(1., 0., -5., 0., 4.), &[-2., -1., 1., 2.]);
+        // The same, shifted to x - 3 so it is only biquadratic once depressed
+        assert_roots(&solve_quartic(1., -12., 49., -78., 40.), &[1., 2., 4., 5.]);
+    }
+
+    #[test]
+    fn torus_hit_from_far_away() {
+        let torus = Torus::new(
+            1.,
+            0.25,
+            Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+        );
+        let mut rec = HitRecord::new(
+            0.,
+            Vec3::new(0., 0., 0.),
+            Vec3::new(0., 0., 0.),
+            Arc::new(Lambertian::new(Vec3::new(0., 0., 0.))),
+        );
+        let r = Ray::new(Vec3::new(0., 0.1, 1000.), Vec3::new(0., 0., -1.));
+        assert!(torus.hit(&r, 0.001, f32::MAX, &mut rec));
+        let p = rec.get_p();
+        let ring = (p.x * p.x + p.z * p.z).sqrt() - 1.;
+        assert!((ring * ring + p.y * p.y).sqrt() - 0.25 < 1e-3);
+        assert!(p.z > 1.);
+        assert!(rec.get_normal().z > 0.9);
+
+        // Straight down through the hole
+        let r = Ray::new(Vec3::new(0., 1000., 0.), Vec3::new(0., -1., 0.));
+        assert!(!torus.hit(&r, 0.001, f32::MAX, &mut rec));
+    }
+}
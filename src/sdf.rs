@@ -0,0 +1,382 @@
+use crate::hitables::*;
+use crate::materials::*;
+use crate::mylib::*;
+use crate::vecmath::Vec3;
+use std::sync::Arc;
+
+/// A signed distance function: how far 'p' is from the closest point
+/// of the surface, negative inside. Nodes which bend space can only
+/// guarantee a lower bound, which is enough for sphere tracing as long
+/// as it never overestimates
+pub trait DistanceFunction {
+    fn distance(&self, p: Vec3) -> f32;
+    /// A box the surface never leaves
+    fn bounds(&self) -> Aabb;
+}
+
+type Sdf = Arc<dyn DistanceFunction + Sync + Send>;
+
+pub struct SdfSphere {
+    center: Vec3,
+    radius: f32,
+}
+
+impl SdfSphere {
+    pub fn new(center: Vec3, radius: f32) -> Self {
+        Self { center, radius }
+    }
+}
+
+impl DistanceFunction for SdfSphere {
+    fn distance(&self, p: Vec3) -> f32 {
+        (p - self.center).length() - self.radius
+    }
+    fn bounds(&self) -> Aabb {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - r, self.center + r)
+    }
+}
+
+/// A box with edges rounded by 'rounding', which is taken from its size
+pub struct SdfBox {
+    center: Vec3,
+    half_size: Vec3,
+    rounding: f32,
+}
+
+impl SdfBox {
+    pub fn new(center: Vec3, half_size: Vec3, rounding: f32) -> Self {
+        Self {
+            center,
+            half_size,
+            rounding,
+        }
+    }
+}
+
+impl DistanceFunction for SdfBox {
+    fn distance(&self, p: Vec3) -> f32 {
+        let r = Vec3::new(self.rounding, self.rounding, self.rounding);
+        let p = p - self.center;
+        let q = Vec3::new(p.x.abs(), p.y.abs(), p.z.abs()) - self.half_size + r;
+        let outside = Vec3::new(q.x.max(0.), q.y.max(0.), q.z.max(0.)).length();
+        let inside = q.x.max(q.y).max(q.z).min(0.);
+        outside + inside - self.rounding
+    }
+    fn bounds(&self) -> Aabb {
+        Aabb::new(self.center - self.half_size, self.center + self.half_size)
+    }
+}
+
+/// A torus lying on the xz plane around 'center'
+pub struct SdfTorus {
+    center: Vec3,
+    major_radius: f32,
+    minor_radius: f32,
+}
+
+impl SdfTorus {
+    pub fn new(center: Vec3, major_radius: f32, minor_radius: f32) -> Self {
+        Self {
+            center,
+            major_radius,
+            minor_radius,
+        }
+    }
+}
+
+impl DistanceFunction for SdfTorus {
+    fn distance(&self, p: Vec3) -> f32 {
+        let p = p - self.center;
+        let ring = (p.x * p.x + p.z * p.z).sqrt() - self.major_radius;
+        (ring * ring + p.y * p.y).sqrt() - self.minor_radius
+    }
+    fn bounds(&self) -> Aabb {
+        let r = self.major_radius + self.minor_radius;
+        let e = Vec3::new(r, self.minor_radius, r);
+        Aabb::new(self.center - e, self.center + e)
+    }
+}
+
+/// The Mandelbulb fractal, of radius a bit more than 1 around the origin.
+/// The distance is only estimated from how fast the iteration escapes
+pub struct Mandelbulb {
+    power: f32,
+    iterations: usize,
+}
+
+impl Mandelbulb {
+    pub fn new(power: f32, iterations: usize) -> Self {
+        Self { power, iterations }
+    }
+}
+
+impl DistanceFunction for Mandelbulb {
+    fn distance(&self, p: Vec3) -> f32 {
+        let mut z = p;
+        let mut dr = 1.;
+        let mut r = 0.;
+        for _ in 0..self.iterations {
+            r = z.length();
+            if r > 2. {
+                break;
+            }
+            if r == 0. {
+                // 0^power is 0 and the angles have no meaning there
+                z = p;
+                dr = 1.;
+                continue;
+            }
+            // z = z^power + p, in spherical coordinates
+            let theta = (z.z / r).acos() * self.power;
+            let phi = z.y.atan2(z.x) * self.power;
+            dr = r.powf(self.power - 1.) * self.power * dr + 1.;
+            let zr = r.powf(self.power);
+            z = Vec3::new(
+                theta.sin() * phi.cos(),
+                phi.sin() * theta.sin(),
+                theta.cos(),
+            ) * zr
+                + p;
+        }
+        if r == 0. {
+            // Only the origin stays there, which is inside the set
+            return -1e-4;
+        }
+        0.5 * r.ln() * r / dr
+    }
+    fn bounds(&self) -> Aabb {
+        Aabb::new(Vec3::new(-1.2, -1.2, -1.2), Vec3::new(1.2, 1.2, 1.2))
+    }
+}
+
+/// Joins two shapes, filling the corner where they meet with a
+/// rounded blend about 'k' wide
+pub struct SmoothUnion {
+    a: Sdf,
+    b: Sdf,
+    k: f32,
+}
+
+impl SmoothUnion {
+    pub fn new(a: Sdf, b: Sdf, k: f32) -> Self {
+        Self { a, b, k }
+    }
+}
+
+impl DistanceFunction for SmoothUnion {
+    fn distance(&self, p: Vec3) -> f32 {
+        let d1 = self.a.distance(p);
+        let d2 = self.b.distance(p);
+        if self.k <= 0. {
+            return d1.min(d2);
+        }
+        let h = (0.5 + 0.5 * (d2 - d1) / self.k).clamp(0., 1.);
+        d2 * (1. - h) + d1 * h - self.k * h * (1. - h)
+    }
+    /// The blend can push the surface out by at most k/4
+    fn bounds(&self) -> Aabb {
+        self.a.bounds().union(&self.b.bounds()).grow(self.k.max(0.) / 4.)
+    }
+}
+
+/// Copies of a shape every 'spacing' units, 'count' of them on each
+/// side of the original along every axis
+pub struct Repeat {
+    child: Sdf,
+    spacing: Vec3,
+    count: [i32; 3],
+}
+
+impl Repeat {
+    pub fn new(child: Sdf, spacing: Vec3, count: [i32; 3]) -> Self {
+        Self {
+            child,
+            spacing,
+            count,
+        }
+    }
+}
+
+impl DistanceFunction for Repeat {
+    /// Brings 'p' back to the copy closest to it
+    fn distance(&self, p: Vec3) -> f32 {
+        let fold = |v: f32, s: f32, c: i32| {
+            if s <= 0. {
+                v
+            } else {
+                v - s * (v / s).round().clamp(-c as f32, c as f32)
+            }
+        };
+        self.child.distance(Vec3::new(
+            fold(p.x, self.spacing.x, self.count[0]),
+            fold(p.y, self.spacing.y, self.count[1]),
+            fold(p.z, self.spacing.z, self.count[2]),
+        ))
+    }
+    fn bounds(&self) -> Aabb {
+        let b = self.child.bounds();
+        let e = Vec3::new(
+            self.spacing.x * self.count[0] as f32,
+            self.spacing.y * self.count[1] as f32,
+            self.spacing.z * self.count[2] as f32,
+        );
+        Aabb::new(b.min - e, b.max + e)
+    }
+}
+
+// Distance from the y axis of the farthest corner of the box
+fn xz_radius(b: &Aabb) -> f32 {
+    b.corners()
+        .iter()
+        .map(|c| (c.x * c.x + c.z * c.z).sqrt())
+        .fold(0., f32::max)
+}
+
+/// Rotates the shape around the y axis by 'rate' radians for each
+/// unit of height
+pub struct Twist {
+    child: Sdf,
+    rate: f32,
+    // How much the twist can stretch distances, which are divided by it
+    lipschitz: f32,
+}
+
+impl Twist {
+    pub fn new(child: Sdf, rate: f32) -> Self {
+        let radius = xz_radius(&child.bounds());
+        Self {
+            child,
+            rate,
+            lipschitz: (1. + (rate * radius).powi(2)).sqrt(),
+        }
+    }
+}
+
+impl DistanceFunction for Twist {
+    fn distance(&self, p: Vec3) -> f32 {
+        let (s, c) = (self.rate * p.y).sin_cos();
+        let q = Vec3::new(c * p.x - s * p.z, p.y, s * p.x + c * p.z);
+        self.child.distance(q) / self.lipschitz
+    }
+    /// Whatever the rotation, the shape stays in the cylinder around
+    /// the y axis that contains its box
+    fn bounds(&self) -> Aabb {
+        let b = self.child.bounds();
+        let radius = xz_radius(&b);
+        Aabb::new(
+            Vec3::new(-radius, b.min.y, -radius),
+            Vec3::new(radius, b.max.y, radius),
+        )
+    }
+}
+
+/// Adds sinusoidal bumps of the given amplitude and frequency to the surface
+pub struct Displace {
+    child: Sdf,
+    amplitude: f32,
+    frequency: f32,
+}
+
+impl Displace {
+    pub fn new(child: Sdf, amplitude: f32, frequency: f32) -> Self {
+        Self {
+            child,
+            amplitude,
+            frequency,
+        }
+    }
+}
+
+impl DistanceFunction for Displace {
+    fn distance(&self, p: Vec3) -> f32 {
+        let f = self.frequency;
+        let bumps = (f * p.x).sin() * (f * p.y).sin() * (f * p.z).sin();
+        // The bumps make the function steeper than a real distance
+        let lipschitz = 1. + self.amplitude.abs() * f * 3f32.sqrt();
+        (self.child.distance(p) + self.amplitude * bumps) / lipschitz
+    }
+    fn bounds(&self) -> Aabb {
+        self.child.bounds().grow(self.amplitude.abs())
+    }
+}
+
+const MAX_STEPS: usize = 256;
+const SURFACE_EPSILON: f32 = 1e-4;
+
+/// Renders a DistanceFunction through sphere tracing: at every step the
+/// distance tells how far we can move along the ray without crossing
+/// the surface, and we stop once it is close enough to 0
+pub struct SdfHitable {
+    sdf: Sdf,
+    bounds: Aabb,
+    material: Arc<dyn Material + Sync + Send>,
+}
+
+impl SdfHitable {
+    pub fn new(sdf: Sdf, material: Arc<dyn Material + Sync + Send>) -> Self {
+        // Slightly bigger, so the surface is never on the box itself
+        let bounds = sdf.bounds().grow(10. * SURFACE_EPSILON);
+        Self {
+            sdf,
+            bounds,
+            material,
+        }
+    }
+    /// Gradient of the distance through central differences
+    fn normal(&self, p: Vec3) -> Vec3 {
+        let e = SURFACE_EPSILON;
+        let dx = Vec3::new(e, 0., 0.);
+        let dy = Vec3::new(0., e, 0.);
+        let dz = Vec3::new(0., 0., e);
+        Vec3::new(
+            self.sdf.distance(p + dx) - self.sdf.distance(p - dx),
+            self.sdf.distance(p + dy) - self.sdf.distance(p - dy),
+            self.sdf.distance(p + dz) - self.sdf.distance(p - dz),
+        )
+        .unit_vector()
+    }
+}
+
+impl Hitable for SdfHitable {
+    /// Only the part of the ray inside the bounds is traced. Rays
+    /// starting inside (like those refracted by glass) march on the
+    /// negated distance, and a ray starting right on the surface uses
+    /// the gradient to know on which side it is going
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let (mut t, t1) = match self.bounds.hit_range(r, t_min, t_max) {
+            Some(range) => range,
+            None => return false,
+        };
+        let ray_length = r.get_direction().length();
+        let start = r.point_at_parameter(t);
+        let d0 = self.sdf.distance(start);
+        let sign = if d0.abs() > SURFACE_EPSILON {
+            d0.signum()
+        } else if self.normal(start).dot(r.get_direction()) > 0. {
+            1.
+        } else {
+            -1.
+        };
+        let t0 = t;
+        for _ in 0..MAX_STEPS {
+            let p = r.point_at_parameter(t);
+            let d = sign * self.sdf.distance(p);
+            // Close to the start we could still be on the surface we left
+            if d < SURFACE_EPSILON && (t - t0) * ray_length > 10. * SURFACE_EPSILON {
+                let outward_normal = self.normal(p);
+                *rec = HitRecord::new(t, p, outward_normal, self.material.clone());
+                rec.set_face_normal(r, &outward_normal);
+                return true;
+            }
+            t += d.max(SURFACE_EPSILON) / ray_length;
+            if t >= t1 {
+                return false;
+            }
+        }
+        false
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
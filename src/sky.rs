@@ -0,0 +1,431 @@
+use crate::distribution::Distribution2D;
+use crate::lights::*;
+use crate::spectrum::xyz_to_srgb;
+use crate::vecmath::Vec3;
+use rand::Rng;
+use std::f32::consts::PI;
+
+/// What a ray sees when it leaves the scene without hitting anything
+pub trait Sky {
+    fn radiance(&self, direction: Vec3) -> Vec3;
+    /// A direction picked towards the bright parts of the sky, so it
+    /// can be looked for with shadow rays like the lights. By default
+    /// the sky is only found by the rays leaving the scene
+    fn sample(&self) -> Option<LightSample> {
+        None
+    }
+    /// The density of sample() picking 'direction'
+    fn pdf(&self, _direction: Vec3) -> f32 {
+        0.
+    }
+}
+
+// Light of the sun reaching the top of the atmosphere, in the units of
+// the sky model (thousands of lux)
+const SUN_ILLUMINANCE: f32 = 128.;
+
+// The angle from the middle of the sun to its border, in degrees
+const SUN_RADIUS: f32 = 0.2665;
+
+// Perez et al. (1993) formula for the distribution of light over the
+// sky, with 'gamma' the angle from the sun
+fn perez(c: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+    (1. + c[0] * (c[1] / cos_theta).exp())
+        * (1. + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+}
+
+// The fraction of sunlight which makes it through the atmosphere, for
+// red, green and blue, when the sun is at 'cos_zenith' from the zenith:
+// scattered away by the air (Rayleigh) and by haze (Angstrom)
+fn sun_transmittance(turbidity: f32, cos_zenith: f32) -> Vec3 {
+    // Relative air mass (Kasten and Young 1989)
+    let zenith = cos_zenith.acos().to_degrees();
+    let air_mass = 1. / (cos_zenith + 0.50572 * (96.07995 - zenith).powf(-1.6364));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let t = |micrometers: f32| {
+        let rayleigh = 0.008735 * micrometers.powf(-4.08);
+        let haze = beta * micrometers.powf(-1.3);
+        (-air_mass * (rayleigh + haze)).exp()
+    };
+    Vec3::new(t(0.61), t(0.55), t(0.465))
+}
+
+/// The clear sky model of Preetham, Shirley and Smits (1999). The
+/// turbidity goes from 2 for a very clear sky to 10 for a hazy one.
+/// Below the horizon is the ground, lit by the sky and the sun with
+/// 'ground_albedo'. The sun itself is not part of it: add sun() to the
+/// lights of the world, so it gets sampled with shadow rays
+pub struct PreethamSky {
+    sun_direction: Vec3,
+    turbidity: f32,
+    ground_albedo: Vec3,
+    // Of the Perez formula, for the luminance Y and the chromaticities
+    // x and y
+    coefficients: [[f32; 5]; 3],
+    // Y, x and y at the zenith, divided by the Perez formula there
+    zenith: [f32; 3],
+    // Light falling on the ground from the sky and the sun
+    ground_irradiance: Vec3,
+    // Scales the physical units down to what the film shows
+    intensity: f32,
+    sun_radius: f32,
+}
+
+impl PreethamSky {
+    /// 'sun_direction' points towards the sun
+    pub fn new(sun_direction: Vec3, turbidity: f32, ground_albedo: Vec3) -> Self {
+        let sun_direction = sun_direction.unit_vector();
+        let t = turbidity;
+        let coefficients = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+        let theta_s = sun_direction.y.clamp(0., 1.).acos();
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_s);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |a: [f32; 4], b: [f32; 4], c: [f32; 4]| {
+            let poly = |k: [f32; 4]| {
+                ((k[0] * theta_s + k[1]) * theta_s + k[2]) * theta_s + k[3]
+            };
+            t * t * poly(a) + t * poly(b) + poly(c)
+        };
+        let x = cubic(
+            [0.00166, -0.00375, 0.00209, 0.],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        );
+        let y = cubic(
+            [0.00275, -0.00610, 0.00317, 0.],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        );
+        let at_zenith = [luminance, x, y];
+        let zenith = [0, 1, 2].map(|i| at_zenith[i] / perez(&coefficients[i], 1., theta_s));
+        let mut sky = Self {
+            sun_direction,
+            turbidity,
+            ground_albedo,
+            coefficients,
+            zenith,
+            ground_irradiance: Vec3::new(0., 0., 0.),
+            intensity: 0.03,
+            sun_radius: SUN_RADIUS.to_radians(),
+        };
+        sky.ground_irradiance = sky.horizontal_irradiance();
+        sky
+    }
+    /// How bright everything is, 1 keeps the physical units
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+    /// The angle from the middle of the sun to its border, in degrees.
+    /// A bigger sun gives softer shadows and the same light
+    pub fn with_sun_size(mut self, degrees: f32) -> Self {
+        self.sun_radius = degrees.to_radians();
+        self
+    }
+    /// The sun matching the sky, as a light
+    pub fn sun(&self) -> Sun {
+        Sun::new(
+            self.sun_direction,
+            self.sun_irradiance() * self.intensity,
+            self.sun_radius,
+        )
+    }
+
+    // The sky without the intensity
+    fn sky(&self, direction: Vec3) -> Vec3 {
+        // The formula blows up towards the horizon
+        let cos_theta = direction.y.max(0.01);
+        let gamma = direction.dot(self.sun_direction).clamp(-1., 1.).acos();
+        let at = |i: usize| self.zenith[i] * perez(&self.coefficients[i], cos_theta, gamma);
+        let (luminance, x, y) = (at(0), at(1), at(2));
+        xyz_to_srgb(Vec3::new(
+            x / y * luminance,
+            luminance,
+            (1. - x - y) / y * luminance,
+        ))
+    }
+
+    // Of a surface facing the sun
+    fn sun_irradiance(&self) -> Vec3 {
+        if self.sun_direction.y <= 0. {
+            return Vec3::new(0., 0., 0.);
+        }
+        sun_transmittance(self.turbidity, self.sun_direction.y) * SUN_ILLUMINANCE
+    }
+
+    // The light falling on a flat ground, adding up the sky over the
+    // upper hemisphere
+    fn horizontal_irradiance(&self) -> Vec3 {
+        const STEPS: usize = 32;
+        let (d_theta, d_phi) = (PI / 2. / STEPS as f32, 2. * PI / (2 * STEPS) as f32);
+        let mut total = self.sun_irradiance() * self.sun_direction.y.max(0.);
+        for i in 0..STEPS {
+            let theta = (i as f32 + 0.5) * d_theta;
+            for j in 0..2 * STEPS {
+                let phi = (j as f32 + 0.5) * d_phi;
+                let direction = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                total += self.sky(direction) * (theta.cos() * theta.sin() * d_theta * d_phi);
+            }
+        }
+        total
+    }
+}
+
+impl Sky for PreethamSky {
+    fn radiance(&self, direction: Vec3) -> Vec3 {
+        let direction = direction.unit_vector();
+        let radiance = if direction.y >= 0. {
+            self.sky(direction)
+        } else {
+            self.ground_albedo * self.ground_irradiance / PI
+        };
+        radiance * self.intensity
+    }
+}
+
+/// A disk of light infinitely far away, picked uniformly over the cone
+/// it covers when looking for it with shadow rays
+pub struct Sun {
+    direction: Vec3,
+    radiance: Vec3,
+    cos_radius: f32,
+}
+
+impl Sun {
+    /// 'irradiance' is what a surface facing the sun gets, whatever
+    /// its 'radius', in radians
+    pub fn new(direction: Vec3, irradiance: Vec3, radius: f32) -> Self {
+        let cos_radius = radius.cos();
+        let solid_angle = 2. * PI * (1. - cos_radius);
+        Self {
+            direction: direction.unit_vector(),
+            radiance: irradiance / solid_angle,
+            cos_radius,
+        }
+    }
+}
+
+impl Light for Sun {
+    fn sample(&self, _p: Vec3) -> Option<LightSample> {
+        if self.radiance.x <= 0. && self.radiance.y <= 0. && self.radiance.z <= 0. {
+            return None;
+        }
+        let mut rng = rand::thread_rng();
+        let cos_theta = 1. - rng.gen::<f32>() * (1. - self.cos_radius);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * rng.gen::<f32>();
+        let (t, b) = self.direction.orthonormal_basis();
+        let direction =
+            t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + self.direction * cos_theta;
+        let solid_angle = 2. * PI * (1. - self.cos_radius);
+        Some(LightSample {
+            direction,
+            distance: f32::MAX,
+            radiance: self.radiance * solid_angle,
+            pdf: 1. / solid_angle,
+        })
+    }
+    fn emitted(&self, direction: Vec3) -> Vec3 {
+        if direction.unit_vector().dot(self.direction) >= self.cos_radius {
+            self.radiance
+        } else {
+            Vec3::new(0., 0., 0.)
+        }
+    }
+    fn pdf(&self, _p: Vec3, direction: Vec3) -> f32 {
+        if direction.unit_vector().dot(self.direction) >= self.cos_radius {
+            1. / (2. * PI * (1. - self.cos_radius))
+        } else {
+            0.
+        }
+    }
+}
+
+/// The light coming from every direction, read from a picture in
+/// latitude-longitude layout like most HDR environments: the top row is
+/// straight up and u goes once around the vertical axis. Directions are
+/// picked in proportion to the brightness of the pixels, so a small
+/// and bright sun in the picture is found by the shadow rays instead of
+/// only by the few rays lucky enough to hit it
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    // Linear values row by row from the top
+    pixels: Vec<Vec3>,
+    distribution: Distribution2D,
+    intensity: f32,
+    // Around the vertical axis, in radians
+    rotation: f32,
+}
+
+impl EnvironmentMap {
+    /// Loads a picture whose values are used as they are, so it should
+    /// be an HDR one which is already linear
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> image::ImageResult<Self> {
+        let rgb = image::open(path)?.to_rgb32f();
+        let pixels = rgb.pixels().map(|p| Vec3::new(p[0], p[1], p[2])).collect();
+        Ok(Self::from_pixels(rgb.width() as usize, rgb.height() as usize, pixels))
+    }
+    /// A picture made in code, 'pixels' holds linear values row by row
+    /// from the top
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Vec3>) -> Self {
+        assert!(
+            width > 0 && height > 0 && pixels.len() == width * height,
+            "An EnvironmentMap needs width * height pixels"
+        );
+        // The rows near the poles cover a smaller part of the sphere
+        let weights: Vec<f32> = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let theta = ((i / width) as f32 + 0.5) / height as f32 * PI;
+                (0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z) * theta.sin()
+            })
+            .collect();
+        Self {
+            width,
+            height,
+            distribution: Distribution2D::new(width, height, &weights),
+            pixels,
+            intensity: 1.,
+            rotation: 0.,
+        }
+    }
+    /// Multiplies every pixel
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+    /// Turns the picture around the vertical axis, in degrees
+    pub fn with_rotation(mut self, degrees: f32) -> Self {
+        self.rotation = degrees.to_radians();
+        self
+    }
+
+    // Where a direction of the world is in the picture
+    fn uv(&self, direction: Vec3) -> (f32, f32) {
+        let d = direction.unit_vector();
+        let (sin, cos) = self.rotation.sin_cos();
+        let (x, z) = (cos * d.x + sin * d.z, cos * d.z - sin * d.x);
+        let phi = z.atan2(x) + PI;
+        // Better than acos() close to the poles
+        let theta = (x * x + z * z).sqrt().atan2(d.y);
+        (phi / (2. * PI), theta / PI)
+    }
+    // The direction of the world at (u, v) of the picture
+    fn direction(&self, u: f32, v: f32) -> Vec3 {
+        let (phi, theta) = (u * 2. * PI - PI, v * PI);
+        let (x, z) = (theta.sin() * phi.cos(), theta.sin() * phi.sin());
+        let (sin, cos) = self.rotation.sin_cos();
+        Vec3::new(cos * x - sin * z, theta.cos(), sin * x + cos * z)
+    }
+    // Going from the square of the picture to the sphere stretches
+    // an area by 2 pi^2 sin(theta)
+    fn solid_angle_pdf(pdf: f32, v: f32) -> f32 {
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0. {
+            0.
+        } else {
+            pdf / (2. * PI * PI * sin_theta)
+        }
+    }
+}
+
+impl Sky for EnvironmentMap {
+    /// The closest pixel, the same value the distribution is built
+    /// from so the sampling matches the picture exactly
+    fn radiance(&self, direction: Vec3) -> Vec3 {
+        let (u, v) = self.uv(direction);
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.pixels[x + y * self.width] * self.intensity
+    }
+    fn sample(&self) -> Option<LightSample> {
+        let mut rng = rand::thread_rng();
+        let (u, v, pdf) = self.distribution.sample(rng.gen(), rng.gen());
+        let pdf = Self::solid_angle_pdf(pdf, v);
+        if pdf <= 0. {
+            return None;
+        }
+        let direction = self.direction(u, v);
+        Some(LightSample {
+            direction,
+            distance: f32::MAX,
+            radiance: self.radiance(direction) / pdf,
+            pdf,
+        })
+    }
+    fn pdf(&self, direction: Vec3) -> f32 {
+        let (u, v) = self.uv(direction);
+        Self::solid_angle_pdf(self.distribution.pdf(u, v), v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environment() -> EnvironmentMap {
+        let pixels = (0..32)
+            .map(|i| Vec3::new((i % 5) as f32, (i % 3) as f32, 0.5))
+            .collect();
+        EnvironmentMap::from_pixels(8, 4, pixels).with_rotation(30.)
+    }
+
+    #[test]
+    fn environment_samples_have_the_density_of_pdf() {
+        let sky = environment();
+        for _ in 0..1000 {
+            let sample = sky.sample().unwrap();
+            let pdf = sky.pdf(sample.direction);
+            assert!((sample.pdf - pdf).abs() < 1e-3 * pdf, "{} != {}", sample.pdf, pdf);
+        }
+    }
+
+    #[test]
+    fn environment_pdf_integrates_to_one() {
+        let sky = environment();
+        let (n_theta, n_phi) = (400, 800);
+        let mut total = 0.;
+        for i in 0..n_theta {
+            let theta = (i as f32 + 0.5) / n_theta as f32 * PI;
+            for j in 0..n_phi {
+                let phi = (j as f32 + 0.5) / n_phi as f32 * 2. * PI;
+                let direction = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                total += sky.pdf(direction) * theta.sin();
+            }
+        }
+        total *= PI / n_theta as f32 * 2. * PI / n_phi as f32;
+        assert!((total - 1.).abs() < 1e-2, "{}", total);
+    }
+}
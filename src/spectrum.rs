@@ -0,0 +1,159 @@
+use crate::vecmath::Vec3;
+
+// Spectral mode: instead of red, green and blue, the three channels of
+// a Vec3 carry the light at three wavelengths picked for each path
+
+/// The visible range we sample, in nanometers
+pub const LAMBDA_MIN: f32 = 380.;
+pub const LAMBDA_MAX: f32 = 720.;
+
+/// Wavelength at which indices of refraction are usually given, the
+/// sodium D line, used for dispersive materials when rendering in RGB
+pub const SODIUM_D_LINE: f32 = 589.3;
+
+/// Hero wavelength sampling (Wilkie et al. 2014): 'u' picks the first
+/// wavelength uniformly over the range, the other two are spread
+/// evenly from it, wrapping around
+pub fn sample_wavelengths(u: f32) -> Vec3 {
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    let hero = u * range;
+    let at = |i: f32| LAMBDA_MIN + (hero + i * range / 3.) % range;
+    Vec3::new(at(0.), at(1.), at(2.))
+}
+
+/// Keeps only the hero wavelength, for when the path cannot be shared
+/// by all of them, like the different directions of light dispersed by
+/// a prism. The hero now stands for all three, so it counts three times
+pub fn terminate_secondary(v: Vec3) -> Vec3 {
+    Vec3::new(v.x * 3., 0., 0.)
+}
+
+// Smits' (1999) spectra of the RGB primaries and their mixes, in 10
+// bins over the visible range
+const SMITS_WHITE: [f32; 10] = [1., 1., 0.9999, 0.9993, 0.9992, 0.9998, 1., 1., 1., 1.];
+const SMITS_CYAN: [f32; 10] = [0.971, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0., 0., 0.];
+const SMITS_MAGENTA: [f32; 10] = [1., 1., 0.9685, 0.2229, 0., 0.0458, 0.8369, 1., 1., 0.9959];
+const SMITS_YELLOW: [f32; 10] = [0.0001, 0., 0.1088, 0.6651, 1., 1., 0.9996, 0.9586, 0.9685, 0.984];
+const SMITS_RED: [f32; 10] = [0.1012, 0.0515, 0., 0., 0., 0., 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f32; 10] = [0., 0., 0.0273, 0.7937, 1., 0.9418, 0.1719, 0., 0., 0.0025];
+const SMITS_BLUE: [f32; 10] = [1., 1., 0.8916, 0.3323, 0., 0., 0.0003, 0.0369, 0.0483, 0.0496];
+
+/// A smooth spectrum with the given color, at a single wavelength.
+/// Also works for things which are not colors but are given for red,
+/// green and blue, like the indices of refraction of metals
+pub fn spectrum_at(rgb: Vec3, lambda: f32) -> f32 {
+    let bin = (((lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10.) as usize).min(9);
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+    // The smallest channel is white, the rest is made of the mix
+    // of the other two and the biggest primary
+    if r <= g && r <= b {
+        r * SMITS_WHITE[bin]
+            + if g <= b {
+                (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+            } else {
+                (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+            }
+    } else if g <= r && g <= b {
+        g * SMITS_WHITE[bin]
+            + if r <= b {
+                (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+            } else {
+                (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+            }
+    } else {
+        b * SMITS_WHITE[bin]
+            + if r <= g {
+                (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+            } else {
+                (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+            }
+    }
+}
+
+/// Turns an RGB color (a reflectance, or the color of a light) into a
+/// spectrum and reads it at the three wavelengths
+pub fn rgb_to_spectrum(rgb: Vec3, wavelengths: Vec3) -> Vec3 {
+    Vec3::new(
+        spectrum_at(rgb, wavelengths.x),
+        spectrum_at(rgb, wavelengths.y),
+        spectrum_at(rgb, wavelengths.z),
+    )
+}
+
+// Piecewise gaussian used by the fit of the color matching functions
+fn gaussian(x: f32, mu: f32, sigma_left: f32, sigma_right: f32) -> f32 {
+    let sigma = if x < mu { sigma_left } else { sigma_right };
+    (-0.5 * ((x - mu) / sigma).powi(2)).exp()
+}
+
+/// The CIE 1931 color matching functions, through the analytic fit of
+/// Wyman, Sloan and Shirley (2013)
+fn cie_xyz(lambda: f32) -> Vec3 {
+    Vec3::new(
+        1.056 * gaussian(lambda, 599.8, 37.9, 31.0) + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2),
+        0.821 * gaussian(lambda, 568.8, 46.9, 40.5) + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1),
+        1.217 * gaussian(lambda, 437.0, 11.8, 36.0) + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8),
+    )
+}
+
+// Integral of the y matching function over the range, so that a
+// spectrum of 1 everywhere has a luminance of 1
+const CIE_Y_INTEGRAL: f32 = 106.912;
+
+// Linear sRGB of a spectrum of 1 everywhere, which we divide out so it
+// comes out white: a simple white balance from the equal energy white
+// to the D65 white of sRGB
+const EQUAL_ENERGY_RGB: Vec3 = Vec3 {
+    x: 1.2006,
+    y: 0.9496,
+    z: 0.9079,
+};
+
+/// Estimates the CIE XYZ color of the light carried at the three
+/// wavelengths, each picked with a uniform density
+pub fn spectrum_to_xyz(radiance: Vec3, wavelengths: Vec3) -> Vec3 {
+    let xyz = cie_xyz(wavelengths.x) * radiance.x
+        + cie_xyz(wavelengths.y) * radiance.y
+        + cie_xyz(wavelengths.z) * radiance.z;
+    xyz * ((LAMBDA_MAX - LAMBDA_MIN) / (3. * CIE_Y_INTEGRAL))
+}
+
+/// The color of a reflectance known for every wavelength, for effects
+/// which only make sense on the spectrum when rendering in RGB
+pub fn reflectance_to_rgb(reflectance: impl Fn(f32) -> f32) -> Vec3 {
+    const SAMPLES: usize = 16;
+    let step = (LAMBDA_MAX - LAMBDA_MIN) / SAMPLES as f32;
+    let mut xyz = Vec3::new(0., 0., 0.);
+    for i in 0..SAMPLES {
+        let lambda = LAMBDA_MIN + (i as f32 + 0.5) * step;
+        xyz += cie_xyz(lambda) * reflectance(lambda);
+    }
+    // Saturated colors can fall outside of sRGB
+    let rgb = xyz_to_rgb(xyz * (step / CIE_Y_INTEGRAL));
+    Vec3::new(
+        rgb.x.clamp(0., 1.),
+        rgb.y.clamp(0., 1.),
+        rgb.z.clamp(0., 1.),
+    )
+}
+
+/// Linear sRGB of a color given in CIE XYZ, with the D65 white
+pub fn xyz_to_srgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+        -0.969266 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+        0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z,
+    )
+}
+
+/// Linear sRGB, what the film stores, of the color estimated by
+/// spectrum_to_xyz()
+pub fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    let rgb = xyz_to_srgb(xyz);
+    Vec3::new(
+        rgb.x / EQUAL_ENERGY_RGB.x,
+        rgb.y / EQUAL_ENERGY_RGB.y,
+        rgb.z / EQUAL_ENERGY_RGB.z,
+    )
+}
@@ -0,0 +1,428 @@
+use crate::noise::Noise;
+use crate::vecmath::Vec3;
+use std::sync::Arc;
+
+/// Something that gives a color, or any other value stored in a Vec3,
+/// for each point of a surface. Materials which only need a number,
+/// like a roughness, read the first channel
+pub trait Texture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3;
+    /// The value averaged over the area a pixel covers, for textures
+    /// which would alias otherwise. By default the footprint is ignored
+    fn filtered(&self, u: f32, v: f32, p: &Vec3, _footprint: &UvFootprint) -> Vec3 {
+        self.value(u, v, p)
+    }
+}
+
+/// How much u and v change from a pixel to the next one along x and
+/// y around a hit, all zero when it is not known
+#[derive(Clone, Copy, Default, Debug)]
+pub struct UvFootprint {
+    pub dudx: f32,
+    pub dvdx: f32,
+    pub dudy: f32,
+    pub dvdy: f32,
+}
+
+/// The same value everywhere
+pub struct SolidColor {
+    color: Vec3,
+}
+
+impl SolidColor {
+    pub fn new(color: Vec3) -> Self {
+        Self { color }
+    }
+    /// The same number on every channel
+    pub fn scalar(value: f32) -> Self {
+        Self::new(Vec3::new(value, value, value))
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+/// Squares alternating between two textures, 'scale' of them for each
+/// unit of u and v
+pub struct CheckerTexture {
+    even: Arc<dyn Texture + Sync + Send>,
+    odd: Arc<dyn Texture + Sync + Send>,
+    scale: f32,
+}
+
+impl CheckerTexture {
+    pub fn new(
+        even: Arc<dyn Texture + Sync + Send>,
+        odd: Arc<dyn Texture + Sync + Send>,
+        scale: f32,
+    ) -> Self {
+        Self { even, odd, scale }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f32, v: f32, p: &Vec3) -> Vec3 {
+        let square = (u * self.scale).floor() as i64 + (v * self.scale).floor() as i64;
+        if square.rem_euclid(2) == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+/// Goes from 'start' at v = 0 to 'end' at v = 1
+pub struct GradientTexture {
+    start: Vec3,
+    end: Vec3,
+}
+
+impl GradientTexture {
+    pub fn new(start: Vec3, end: Vec3) -> Self {
+        Self { start, end }
+    }
+}
+
+impl Texture for GradientTexture {
+    fn value(&self, _u: f32, v: f32, _p: &Vec3) -> Vec3 {
+        let v = v.clamp(0., 1.);
+        self.start * (1. - v) + self.end * v
+    }
+}
+
+/// Colors at positions in [0,1] with linear blends between them, for
+/// turning a number into a color
+#[derive(Clone)]
+pub struct ColorRamp {
+    stops: Vec<(f32, Vec3)>,
+}
+
+impl ColorRamp {
+    /// The stops can be given in any order
+    pub fn new(mut stops: Vec<(f32, Vec3)>) -> Self {
+        assert!(!stops.is_empty(), "A ColorRamp needs at least one color");
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { stops }
+    }
+    /// From 'start' at 0 to 'end' at 1
+    pub fn linear(start: Vec3, end: Vec3) -> Self {
+        Self::new(vec![(0., start), (1., end)])
+    }
+    pub fn at(&self, t: f32) -> Vec3 {
+        let after = self.stops.iter().position(|s| s.0 > t);
+        match after {
+            Some(0) => self.stops[0].1,
+            None => self.stops[self.stops.len() - 1].1,
+            Some(i) => {
+                let (t0, c0) = self.stops[i - 1];
+                let (t1, c1) = self.stops[i];
+                let x = (t - t0) / (t1 - t0);
+                c0 * (1. - x) + c1 * x
+            }
+        }
+    }
+}
+
+/// The patterns a NoiseTexture can draw, all of them giving a number
+/// in [0,1] which is looked up in the ramp
+pub enum NoisePattern {
+    Perlin,
+    Fbm,
+    Turbulence,
+    /// Veins across x, bent by turbulence
+    Marble,
+    /// Rings around the y axis, a little wobbly
+    Wood,
+    /// Cells, 0 at the middle of each growing towards the edges
+    Worley,
+}
+
+/// Solid noise, computed from the point in space rather than u,v so
+/// objects look carved out of it and need no coordinates
+pub struct NoiseTexture {
+    noise: Noise,
+    pattern: NoisePattern,
+    // Features per unit of length
+    scale: f32,
+    octaves: u32,
+    ramp: ColorRamp,
+}
+
+impl NoiseTexture {
+    /// Black to white, 6 octaves where the pattern uses them
+    pub fn new(pattern: NoisePattern, scale: f32) -> Self {
+        Self {
+            noise: Noise::new(0),
+            pattern,
+            scale,
+            octaves: 6,
+            ramp: ColorRamp::linear(Vec3::new(0., 0., 0.), Vec3::new(1., 1., 1.)),
+        }
+    }
+    pub fn with_ramp(mut self, ramp: ColorRamp) -> Self {
+        self.ramp = ramp;
+        self
+    }
+    pub fn with_octaves(mut self, octaves: u32) -> Self {
+        self.octaves = octaves;
+        self
+    }
+    /// A different noise, for two objects that should not look the same
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.noise = Noise::new(seed);
+        self
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f32, _v: f32, p: &Vec3) -> Vec3 {
+        let q = *p * self.scale;
+        let t = match self.pattern {
+            NoisePattern::Perlin => 0.5 * (1. + self.noise.perlin(q)),
+            NoisePattern::Fbm => 0.5 * (1. + self.noise.fbm(q, self.octaves)),
+            NoisePattern::Turbulence => self.noise.turbulence(q, self.octaves),
+            NoisePattern::Marble => {
+                let turbulence = self.noise.turbulence(q, self.octaves);
+                0.5 * (1. + (q.x * std::f32::consts::PI + 8. * turbulence).sin())
+            }
+            NoisePattern::Wood => {
+                let r = (q.x * q.x + q.z * q.z).sqrt();
+                (r + 0.3 * self.noise.fbm(q * 0.5, self.octaves)).rem_euclid(1.)
+            }
+            NoisePattern::Worley => self.noise.worley(q),
+        };
+        self.ramp.at(t.clamp(0., 1.))
+    }
+}
+
+/// What an ImageTexture shows outside of [0,1]
+#[derive(Clone, Copy)]
+pub enum WrapMode {
+    /// Tiles the image
+    Repeat,
+    /// Stretches the pixels of the border
+    Clamp,
+    /// Tiles the image, flipping every other copy so the edges match
+    Mirror,
+}
+
+/// How an ImageTexture reads its pixels
+#[derive(Clone, Copy)]
+pub enum TextureFilter {
+    /// The closest pixel of the full image, which aliases far away
+    Nearest,
+    /// Blends the four closest pixels of the level of the MIP map
+    /// which best matches the footprint
+    Bilinear,
+    /// Blends the bilinear lookups of the two levels around the
+    /// footprint, so the change of level is not visible
+    Trilinear,
+}
+
+// One level of the MIP map, the pixels are linear and row by row from
+// the top
+struct MipLevel {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+}
+
+// Decodes a value stored in sRGB
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// A picture wrapped on the surface through u and v, with v = 0 at the
+/// bottom of it. Each level of the MIP map is half the size of the one
+/// before, down to a single pixel, so far away surfaces read the
+/// average of the pixels they cover
+pub struct ImageTexture {
+    levels: Vec<MipLevel>,
+    wrap: WrapMode,
+    filter: TextureFilter,
+}
+
+impl ImageTexture {
+    /// Loads a PNG, JPEG or HDR picture holding colors. 8 and 16 bit
+    /// pictures are stored in sRGB and turned into linear values
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> image::ImageResult<Self> {
+        Self::load(path, true)
+    }
+    /// Loads a picture holding data rather than colors, like a normal
+    /// map or a roughness, which is used as it is
+    pub fn open_linear<P: AsRef<std::path::Path>>(path: P) -> image::ImageResult<Self> {
+        Self::load(path, false)
+    }
+    fn load<P: AsRef<std::path::Path>>(path: P, srgb: bool) -> image::ImageResult<Self> {
+        let picture = image::open(path)?;
+        // HDR pictures are already linear
+        let decode = srgb
+            && !matches!(
+                picture.color(),
+                image::ColorType::Rgb32F | image::ColorType::Rgba32F
+            );
+        let rgb = picture.to_rgb32f();
+        let pixels = rgb
+            .pixels()
+            .map(|p| {
+                let c = Vec3::new(p[0], p[1], p[2]);
+                if decode {
+                    Vec3::new(srgb_to_linear(c.x), srgb_to_linear(c.y), srgb_to_linear(c.z))
+                } else {
+                    c
+                }
+            })
+            .collect();
+        Ok(Self::from_pixels(rgb.width() as usize, rgb.height() as usize, pixels))
+    }
+    /// A picture made in code, 'pixels' holds linear values row by row
+    /// from the top
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Vec3>) -> Self {
+        assert!(
+            width > 0 && height > 0 && pixels.len() == width * height,
+            "An ImageTexture needs width * height pixels"
+        );
+        let mut texture = Self {
+            levels: vec![MipLevel {
+                width,
+                height,
+                pixels,
+            }],
+            wrap: WrapMode::Repeat,
+            filter: TextureFilter::Trilinear,
+        };
+        texture.build_mip_map();
+        texture
+    }
+    pub fn with_wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap = wrap;
+        self
+    }
+    pub fn with_filter(mut self, filter: TextureFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    // Each new level averages 2x2 blocks of the previous one. Along an
+    // odd size each new pixel covers a bit more than 2, so it takes 3
+    // with weights making every old pixel count as much
+    fn build_mip_map(&mut self) {
+        // The pixels of the previous level covered by pixel 'i' of the
+        // next one, with their weights
+        let taps = |i: usize, from: usize, to: usize| -> Vec<(usize, f32)> {
+            if from == to {
+                vec![(i, 1.)]
+            } else if from.is_multiple_of(2) {
+                vec![(2 * i, 0.5), (2 * i + 1, 0.5)]
+            } else {
+                let n = from as f32;
+                vec![
+                    (2 * i, (to - i) as f32 / n),
+                    (2 * i + 1, to as f32 / n),
+                    (2 * i + 2, (i + 1) as f32 / n),
+                ]
+            }
+        };
+        loop {
+            let last = &self.levels[self.levels.len() - 1];
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let (width, height) = ((last.width / 2).max(1), (last.height / 2).max(1));
+            let mut pixels = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    let mut sum = Vec3::new(0., 0., 0.);
+                    for (sy, wy) in taps(y, last.height, height) {
+                        for (sx, wx) in taps(x, last.width, width) {
+                            sum += last.pixels[sx + sy * last.width] * (wx * wy);
+                        }
+                    }
+                    pixels.push(sum);
+                }
+            }
+            self.levels.push(MipLevel {
+                width,
+                height,
+                pixels,
+            });
+        }
+    }
+
+    // The pixel in column x and row y, outside of the image through
+    // the wrap mode
+    fn texel(&self, level: &MipLevel, x: i64, y: i64) -> Vec3 {
+        let wrap = |i: i64, n: usize| -> usize {
+            let n = n as i64;
+            match self.wrap {
+                WrapMode::Repeat => i.rem_euclid(n) as usize,
+                WrapMode::Clamp => i.clamp(0, n - 1) as usize,
+                WrapMode::Mirror => {
+                    let i = i.rem_euclid(2 * n);
+                    (if i < n { i } else { 2 * n - 1 - i }) as usize
+                }
+            }
+        };
+        level.pixels[wrap(y, level.height) * level.width + wrap(x, level.width)]
+    }
+
+    fn nearest(&self, level: &MipLevel, u: f32, v: f32) -> Vec3 {
+        let x = (u * level.width as f32).floor() as i64;
+        let y = ((1. - v) * level.height as f32).floor() as i64;
+        self.texel(level, x, y)
+    }
+
+    fn bilinear(&self, level: &MipLevel, u: f32, v: f32) -> Vec3 {
+        // Pixel centers are at half integers
+        let x = u * level.width as f32 - 0.5;
+        let y = (1. - v) * level.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        self.texel(level, x0, y0) * ((1. - fx) * (1. - fy))
+            + self.texel(level, x0 + 1, y0) * (fx * (1. - fy))
+            + self.texel(level, x0, y0 + 1) * ((1. - fx) * fy)
+            + self.texel(level, x0 + 1, y0 + 1) * (fx * fy)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _p: &Vec3) -> Vec3 {
+        match self.filter {
+            TextureFilter::Nearest => self.nearest(&self.levels[0], u, v),
+            _ => self.bilinear(&self.levels[0], u, v),
+        }
+    }
+    fn filtered(&self, u: f32, v: f32, p: &Vec3, footprint: &UvFootprint) -> Vec3 {
+        // The longest side of the footprint, in pixels of the full image
+        let (w, h) = (self.levels[0].width as f32, self.levels[0].height as f32);
+        let x = (footprint.dudx * w).hypot(footprint.dvdx * h);
+        let y = (footprint.dudy * w).hypot(footprint.dvdy * h);
+        let width = x.max(y);
+        if width <= 1. {
+            return self.value(u, v, p);
+        }
+        // Level i has pixels 2^i times wider
+        let last = (self.levels.len() - 1) as f32;
+        let level = width.log2().min(last);
+        match self.filter {
+            TextureFilter::Nearest => self.nearest(&self.levels[0], u, v),
+            TextureFilter::Bilinear => self.bilinear(&self.levels[level.round() as usize], u, v),
+            TextureFilter::Trilinear => {
+                let below = level.floor();
+                let t = level - below;
+                let below = below as usize;
+                let above = (below + 1).min(self.levels.len() - 1);
+                self.bilinear(&self.levels[below], u, v) * (1. - t)
+                    + self.bilinear(&self.levels[above], u, v) * t
+            }
+        }
+    }
+}
@@ -32,6 +32,17 @@ impl Vec3 {
     pub fn unit_vector(self) -> Self{
         self / self.length()
     }
+    /// Two unit vectors perpendicular to this one, which must be a
+    /// unit vector too, and to each other
+    pub fn orthonormal_basis(self) -> (Self, Self){
+        let a = if self.x.abs() > 0.9 {
+            Vec3::new(0., 1., 0.)
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let t = self.cross(a).unit_vector();
+        (t, self.cross(t))
+    }
 
 }
 
@@ -65,6 +76,12 @@ impl ops::SubAssign for Vec3 {
         *self = *self - t;
     }
 }
+impl ops::Neg for Vec3 {
+    type Output = Self;
+    fn neg(self) -> Self{
+        self * -1.
+    }
+}
 impl ops::Mul<Vec3> for Vec3 {
     type Output = Self;
     fn mul(self, other: Self) -> Self{
@@ -131,3 +148,161 @@ impl ops::DivAssign<f32> for Vec3 {
         *self = *self / t;
     }
 }
+
+/// A 4x4 matrix stored in row major order, used for affine transforms.
+/// Points are treated as (x,y,z,1) and directions as (x,y,z,0)
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Mat4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(m: [[f32; 4]; 4]) -> Self{
+        Self {m}
+    }
+    pub fn identity() -> Self{
+        Self::new([
+            [1., 0., 0., 0.],
+            [0., 1., 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+    pub fn translation(t: Vec3) -> Self{
+        Self::new([
+            [1., 0., 0., t.x],
+            [0., 1., 0., t.y],
+            [0., 0., 1., t.z],
+            [0., 0., 0., 1.],
+        ])
+    }
+    /// Scale by a different amount on every axis
+    pub fn scaling(s: Vec3) -> Self{
+        Self::new([
+            [s.x, 0., 0., 0.],
+            [0., s.y, 0., 0.],
+            [0., 0., s.z, 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+    /// Rotation of 'degrees' around 'axis', following the right hand rule
+    pub fn rotation(axis: Vec3, degrees: f32) -> Self{
+        let a = axis.unit_vector();
+        let theta = degrees * (std::f32::consts::PI) / 180.;
+        let (s, c) = theta.sin_cos();
+        let t = 1. - c;
+        Self::new([
+            [t * a.x * a.x + c, t * a.x * a.y - s * a.z, t * a.x * a.z + s * a.y, 0.],
+            [t * a.x * a.y + s * a.z, t * a.y * a.y + c, t * a.y * a.z - s * a.x, 0.],
+            [t * a.x * a.z - s * a.y, t * a.y * a.z + s * a.x, t * a.z * a.z + c, 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+    pub fn rotation_y(degrees: f32) -> Self{
+        Self::rotation(Vec3::new(0., 1., 0.), degrees)
+    }
+    pub fn transpose(&self) -> Self{
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[j][i];
+            }
+        }
+        Self::new(m)
+    }
+    /// Inverse through Gauss-Jordan elimination, returns None if
+    /// the matrix is singular (e.g. a scale of 0 on some axis)
+    pub fn inverse(&self) -> Option<Self>{
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+        for col in 0..4 {
+            // Partial pivoting, pick the row with the biggest value
+            let mut pivot = col;
+            for row in (col + 1)..4 {
+                if a[row][col].abs() > a[pivot][col].abs() {
+                    pivot = row;
+                }
+            }
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let d = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= d;
+                inv[col][j] /= d;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= f * a[col][j];
+                        inv[row][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Self::new(inv))
+    }
+    /// Transform a position, translation is applied
+    pub fn transform_point(&self, p: Vec3) -> Vec3{
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+    /// Transform a direction, translation is ignored
+    pub fn transform_vector(&self, v: Vec3) -> Vec3{
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl ops::Mul<Mat4> for Mat4 {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self{
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Self::new(m)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_identity(m: Mat4) {
+        for (i, row) in m.m.iter().enumerate() {
+            for (j, v) in row.iter().enumerate() {
+                let expected = if i == j { 1. } else { 0. };
+                assert!((v - expected).abs() < 1e-5, "m[{}][{}] = {}", i, j, v);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let m = Mat4::translation(Vec3::new(1., -2., 3.))
+            * Mat4::rotation(Vec3::new(1., 2., 0.5), 37.)
+            * Mat4::scaling(Vec3::new(2., 0.5, 3.));
+        let inverse = m.inverse().unwrap();
+        assert_identity(m * inverse);
+        assert_identity(inverse * m);
+    }
+
+    #[test]
+    fn singular_matrix_has_no_inverse() {
+        assert!(Mat4::scaling(Vec3::new(1., 0., 1.)).inverse().is_none());
+    }
+}
@@ -0,0 +1,265 @@
+use crate::hitables::*;
+use crate::materials::*;
+use crate::mylib::*;
+use crate::vecmath::Vec3;
+use rand::Rng;
+use std::io::Read;
+use std::sync::Arc;
+
+// The parts of the ray inside 'boundary' and inside (t_min, t_max),
+// as (t_enter, t_exit) pairs sorted by t
+fn inside_segments(
+    boundary: &(dyn Hitable + Sync + Send),
+    r: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Vec<(f32, f32)> {
+    let mut segments = Vec::new();
+    let mut enter = None;
+    for rec in boundary.hit_all(r, f32::MIN, f32::MAX) {
+        if rec.get_front_face() {
+            enter = Some(rec.get_t());
+        } else if let Some(t0) = enter.take() {
+            let t0 = t0.max(t_min);
+            let t1 = rec.get_t().min(t_max);
+            if t0 < t1 {
+                segments.push((t0, t1));
+            }
+        }
+    }
+    segments
+}
+
+/// Smoke, fog or mist with the same density everywhere inside a
+/// closed boundary. A ray going through it scatters after a random
+/// distance, which is more likely to be short the denser it is
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hitable + Sync + Send>,
+    neg_inv_density: f32,
+    phase_function: Arc<dyn Material + Sync + Send>,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hitable + Sync + Send>, density: f32, albedo: Vec3) -> Self {
+        Self::with_phase_function(boundary, density, Arc::new(Isotropic::new(albedo)))
+    }
+    /// Use a custom material to choose the new direction after scattering
+    pub fn with_phase_function(
+        boundary: Arc<dyn Hitable + Sync + Send>,
+        density: f32,
+        phase_function: Arc<dyn Material + Sync + Send>,
+    ) -> Self {
+        Self {
+            boundary,
+            neg_inv_density: -1. / density,
+            phase_function,
+        }
+    }
+    /// A fog filling the whole scene, up to 'radius' from the origin.
+    /// It has to end somewhere, or no light from the sky would get in
+    pub fn atmosphere(radius: f32, density: f32, albedo: Vec3) -> Self {
+        Self::new(
+            Arc::new(Sphere::new(
+                Vec3::new(0., 0., 0.),
+                radius,
+                Arc::new(Lambertian::new(Vec3::new(0., 0., 0.))),
+            )),
+            density,
+            albedo,
+        )
+    }
+}
+
+impl Hitable for ConstantMedium {
+    /// The boundary may be entered and left more than once, so the
+    /// free flight distance is consumed by each piece inside it in turn
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let ray_length = r.get_direction().length();
+        let mut hit_distance = self.neg_inv_density * rand::thread_rng().gen::<f32>().ln();
+        for (t0, t1) in inside_segments(&*self.boundary, r, t_min.max(0.), t_max) {
+            let distance_inside = (t1 - t0) * ray_length;
+            if hit_distance > distance_inside {
+                hit_distance -= distance_inside;
+                continue;
+            }
+            let t = t0 + hit_distance / ray_length;
+            // The normal means nothing inside a volume
+            *rec = HitRecord::new(
+                t,
+                r.point_at_parameter(t),
+                Vec3::new(1., 0., 0.),
+                self.phase_function.clone(),
+            );
+            rec.set_front_face(true);
+            return true;
+        }
+        false
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+/// A 3D grid of densities, like a cloud or an explosion from a
+/// simulation. Values are read with trilinear interpolation
+pub struct VoxelGrid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    data: Vec<f32>,
+    max_density: f32,
+}
+
+impl VoxelGrid {
+    /// 'data' holds nx * ny * nz densities, x changes fastest, then y, then z
+    pub fn new(nx: usize, ny: usize, nz: usize, data: Vec<f32>) -> Self {
+        assert_eq!(data.len(), nx * ny * nz, "The grid needs nx * ny * nz densities");
+        let max_density = data.iter().cloned().fold(0., f32::max);
+        Self {
+            nx,
+            ny,
+            nz,
+            data,
+            max_density,
+        }
+    }
+    /// Fill the grid with 'f' evaluated at the center of every voxel,
+    /// in coordinates going from 0 to 1 on every axis
+    pub fn from_fn(nx: usize, ny: usize, nz: usize, f: impl Fn(Vec3) -> f32) -> Self {
+        let mut data = Vec::with_capacity(nx * ny * nz);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    data.push(f(Vec3::new(
+                        (x as f32 + 0.5) / nx as f32,
+                        (y as f32 + 0.5) / ny as f32,
+                        (z as f32 + 0.5) / nz as f32,
+                    )));
+                }
+            }
+        }
+        Self::new(nx, ny, nz, data)
+    }
+    /// Reads a raw grid: nx, ny and nz as little endian u32 followed
+    /// by all the densities as little endian f32, in the same order as new()
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let mut bytes = Vec::new();
+        std::fs::File::open(path)?.read_to_end(&mut bytes)?;
+        let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string());
+        if bytes.len() < 12 {
+            return Err(invalid("the file is too short for the header"));
+        }
+        let word = |i: usize| [bytes[4 * i], bytes[4 * i + 1], bytes[4 * i + 2], bytes[4 * i + 3]];
+        let nx = u32::from_le_bytes(word(0)) as usize;
+        let ny = u32::from_le_bytes(word(1)) as usize;
+        let nz = u32::from_le_bytes(word(2)) as usize;
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(invalid("the grid is empty"));
+        }
+        // A corrupt header could ask for more than fits in memory
+        let count = nx
+            .checked_mul(ny)
+            .and_then(|n| n.checked_mul(nz))
+            .ok_or_else(|| invalid("the size of the grid overflows"))?;
+        let size = count
+            .checked_mul(4)
+            .and_then(|n| n.checked_add(12))
+            .ok_or_else(|| invalid("the size of the grid overflows"))?;
+        if bytes.len() != size {
+            return Err(invalid("the size of the file does not match the size of the grid"));
+        }
+        let data = (0..count).map(|i| f32::from_le_bytes(word(3 + i))).collect();
+        Ok(Self::new(nx, ny, nz, data))
+    }
+    pub fn get_max_density(&self) -> f32 {
+        self.max_density
+    }
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.data[x + self.nx * (y + self.ny * z)]
+    }
+    /// Density at 'p', with coordinates from 0 to 1 over the whole grid.
+    /// The values are at the centers of the voxels, like in from_fn()
+    pub fn density(&self, p: Vec3) -> f32 {
+        let coord = |v: f32, n: usize| {
+            let x = (v * n as f32 - 0.5).clamp(0., (n - 1) as f32);
+            let i = (x as usize).min(n.saturating_sub(2));
+            (i, (i + 1).min(n - 1), x - i as f32)
+        };
+        let (x0, x1, fx) = coord(p.x, self.nx);
+        let (y0, y1, fy) = coord(p.y, self.ny);
+        let (z0, z1, fz) = coord(p.z, self.nz);
+        let lerp = |a: f32, b: f32, t: f32| a * (1. - t) + b * t;
+        let c00 = lerp(self.voxel(x0, y0, z0), self.voxel(x1, y0, z0), fx);
+        let c10 = lerp(self.voxel(x0, y1, z0), self.voxel(x1, y1, z0), fx);
+        let c01 = lerp(self.voxel(x0, y0, z1), self.voxel(x1, y0, z1), fx);
+        let c11 = lerp(self.voxel(x0, y1, z1), self.voxel(x1, y1, z1), fx);
+        lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+}
+
+/// A volume whose density changes from place to place, taken from a
+/// VoxelGrid stretched over the box from 'min' to 'max'.
+/// Free flights are sampled with delta tracking: we move by steps
+/// as if the whole box was as dense as its densest voxel, and at the
+/// end of each step we only scatter with probability
+/// density / max_density, otherwise the collision was with nothing
+/// and we keep going. This needs no approximation of the density
+pub struct GridMedium {
+    grid: Arc<VoxelGrid>,
+    min: Vec3,
+    max: Vec3,
+    density_scale: f32,
+    phase_function: Arc<dyn Material + Sync + Send>,
+}
+
+impl GridMedium {
+    pub fn new(
+        grid: Arc<VoxelGrid>,
+        min: Vec3,
+        max: Vec3,
+        density_scale: f32,
+        phase_function: Arc<dyn Material + Sync + Send>,
+    ) -> Self {
+        Self {
+            grid,
+            min,
+            max,
+            density_scale,
+            phase_function,
+        }
+    }
+    fn density(&self, p: Vec3) -> f32 {
+        self.grid.density((p - self.min) / (self.max - self.min)) * self.density_scale
+    }
+}
+
+impl Hitable for GridMedium {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord) -> bool {
+        let majorant = self.grid.get_max_density() * self.density_scale;
+        if majorant <= 0. {
+            return false;
+        }
+        let bounds = Aabb::new(self.min, self.max);
+        let (mut t, t1) = match bounds.hit_range(r, t_min.max(0.), t_max) {
+            Some(range) => range,
+            None => return false,
+        };
+        let ray_length = r.get_direction().length();
+        let mut rng = rand::thread_rng();
+        loop {
+            t -= (1. - rng.gen::<f32>()).ln() / (majorant * ray_length);
+            if t >= t1 {
+                return false;
+            }
+            let p = r.point_at_parameter(t);
+            if rng.gen::<f32>() * majorant < self.density(p) {
+                *rec = HitRecord::new(t, p, Vec3::new(1., 0., 0.), self.phase_function.clone());
+                rec.set_front_face(true);
+                return true;
+            }
+        }
+    }
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+}